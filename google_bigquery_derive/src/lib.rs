@@ -83,7 +83,7 @@ fn implement_derive_has_big_query_client(ast: &syn::DeriveInput) -> proc_macro2:
 
 //region BigDataTable derive
 #[proc_macro_derive(BigDataTable,
-attributes(primary_key, client, db_name, db_ignore, required))]
+attributes(primary_key, client, db_name, db_ignore, required, snapshot, version))]
 pub fn big_data_table(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let ast = syn::parse(input).unwrap();
     let tokens = implement_derive(&ast);
@@ -107,21 +107,34 @@ fn implement_big_data_table_base_trait(table_ident: &proc_macro2::Ident,
     let table_name = get_table_name(&ast);
     let pk_ty = &primary_key.ty;
     let client_field = get_client_field(&ast);
+    let snapshot_field = get_snapshot_field(ast);
+    let version_field = get_version_field(ast);
 
     let mut db_fields = get_fields(&ast.data);
     db_fields.retain(|f| f.local_name != client_field.local_name);
+    if let Some(snapshot_field) = &snapshot_field {
+        db_fields.retain(|f| f.local_name != snapshot_field.local_name);
+    }
 
     let get_pk_name = get_get_pk_name(primary_key);
     let get_pk_value = get_get_pk_value(primary_key);
 
     let get_field_name = get_get_field_name(ast, &db_fields);
     let get_query_fields = get_get_query_fields(&db_fields);
-    let write_from_table_row = get_write_from_table_row(&db_fields);
+    let write_from_table_row = get_write_from_table_row(table_ident, &db_fields, &snapshot_field);
     let get_table_name = get_get_table_name(&table_name);
     let create_with_pk = get_create_with_pk(&primary_key, &client_field);
-    let create_from_table_row = get_create_from_table_row(&pk_ty);
+    let create_from_table_row = get_create_from_table_row(table_ident, &pk_ty);
     let get_all_query_parameters = get_get_all_query_parameters(&db_fields);
+    let get_snapshot = get_get_snapshot(&snapshot_field);
+    let has_snapshot = get_has_snapshot(table_ident, &snapshot_field);
+    let version_functions = get_version_functions(&version_field);
+    let columns = get_columns(ast, table_ident, &db_fields);
+    let struct_parameter = get_struct_parameter(table_ident, &db_fields);
     quote::quote! {
+        #columns
+        #struct_parameter
+        #has_snapshot
         impl<'a> BigDataTableHasPk<#pk_ty> for #table_ident<'a> {
             #get_pk_name
             #get_pk_value
@@ -136,6 +149,8 @@ fn implement_big_data_table_base_trait(table_ident: &proc_macro2::Ident,
             #create_from_table_row
             #write_from_table_row
             #get_all_query_parameters
+            #get_snapshot
+            #version_functions
         }
     }
 }
@@ -198,44 +213,32 @@ fn get_get_all_query_parameters(db_fields: &Vec<Field>) -> proc_macro2::TokenStr
     }
 }
 
-fn get_write_from_table_row(db_fields: &Vec<Field>) -> proc_macro2::TokenStream {
-    fn get_write_from_table_row_single_field(field: &Field) -> proc_macro2::TokenStream {
+fn get_write_from_table_row(table_ident: &proc_macro2::Ident, db_fields: &Vec<Field>, snapshot_field: &Option<Field>) -> proc_macro2::TokenStream {
+    fn get_write_from_table_row_single_field(struct_name: &str, field: &Field) -> proc_macro2::TokenStream {
         let field_ident = &field.field_ident;
         let field_name = &field.db_name;
         let field_ty = &field.ty;
-        // if(field.ty == chrono::D)
-        // let parse_fn_tok = quote::quote!{
-        //
-        //     fn parse_value<T: BigDataValueType<#field_ty>>(v: &String) -> T {
-        //         v.to_value()
-        //         // v.parse()
-        //             .expect(format!("could not parse field: {} with value {}", stringify!(#field_name), v)
-        //                 .as_str())
-        //     };
-        //
-        //     // parse()
-        //     // .expect(format!("could not parse field: {} with value {}", stringify!(#field_name), v)
-        //     //     .as_str())
-        // };
+        let parse_error = quote::quote! {
+            |e| google_bigquery::Error::Parse {
+                struct_name: #struct_name.to_string(),
+                field: #field_name.to_string(),
+                value: Some(v.to_string()),
+                message: e.to_string(),
+            }
+        };
         if field.required {
             quote::quote! {
-                // println!("get_write_from_table_row_single_field: field_name: (1) {}", #field_name);
-                let index = *index_to_name_mapping.get(#field_name)
-                    .expect(format!("could not find index for field in mapping!: (1) {}", #field_name).as_str());
-
                 {
-                    self.#field_ident = match row.f.as_ref()
-                        .expect("row.f is None (1)")
-                        [index]
-                        .v.as_ref() {
-                        // Some(v)=> parse_value(v),
-                        // Some(v)=> v.to_value()
-                        // Some(v)=>todo!(),
-                        Some(v)=> #field_ty::from_bigquery_value(v)
-                            .expect(format!("could not parse required field: {} with value {}", stringify!(#field_name), v)
-                                .as_str()),
-                        // Some(v)=> v.to_value(),
-                        None => panic!("field is required but is None: {}", #field_name)
+                    let value = google_bigquery::utils::get_table_row_value(row, index_to_name_mapping, #struct_name, #field_name)?;
+                    self.#field_ident = match value {
+                        Some(v) => #field_ty::from_bigquery_value(v)
+                            .map_err(#parse_error)?,
+                        None => return Err(google_bigquery::Error::Parse {
+                            struct_name: #struct_name.to_string(),
+                            field: #field_name.to_string(),
+                            value: None,
+                            message: "the field is required but the value is NULL".to_string(),
+                        }),
                     };
                 }
             }
@@ -244,21 +247,11 @@ fn get_write_from_table_row(db_fields: &Vec<Field>) -> proc_macro2::TokenStream
                 .expect(&format!("could not extract type from option: {}->{:?}", field_name, field_ty));
 
             quote::quote! {
-                // println!("get_write_from_table_row_single_field: field_name: (2) {} at index: {}", #field_name, index);
-                let index = *index_to_name_mapping.get(#field_name)
-                    .expect(format!("could not find index for field in mapping!: (2) {}", #field_name).as_str());
-
                 {
-                    self.#field_ident = match row.f.as_ref()
-                        .expect("row.f is None (1)")
-                        [index].v.as_ref() {
-                        // Some(v) => Some(v.to_value()),
-                        // Some(v) => v.to_opt_value()
-                        // Some(v)=> todo!()
+                    let value = google_bigquery::utils::get_table_row_value(row, index_to_name_mapping, #struct_name, #field_name)?;
+                    self.#field_ident = match value {
                         Some(v) => Option::<#field_option_ty>::from_bigquery_value(v)
-                            .expect(format!("could not parse field: {} with value {}", stringify!(#field_name), v).as_str())
-                        ,
-                        // Some(v) => Some(parse_value(v)),
+                            .map_err(#parse_error)?,
                         None => None
                     };
                 }
@@ -266,34 +259,72 @@ fn get_write_from_table_row(db_fields: &Vec<Field>) -> proc_macro2::TokenStream
         }
     }
 
-    let tokens: Vec<proc_macro2::TokenStream> = db_fields.iter().map(|field| get_write_from_table_row_single_field(field)).collect();
-    quote::quote! {
-        fn write_from_table_row(&mut self, row: &google_bigquery2::api::TableRow, index_to_name_mapping: &std::collections::HashMap<String, usize>) -> Result<(), Box<dyn std::error::Error>> {
-            #(#tokens)*
-            Ok(())
+    let struct_name = table_ident.to_string();
+    let tokens: Vec<proc_macro2::TokenStream> = db_fields.iter().map(|field| {
+        let field_name = &field.db_name;
+        let write_field = get_write_from_table_row_single_field(&struct_name, field);
+        quote::quote! {
+            if columns.map_or(true, |columns| columns.iter().any(|column| column == #field_name)) {
+                #write_field
+            }
+        }
+    }).collect();
+    match snapshot_field {
+        Some(snapshot_field) => {
+            let snapshot_ident = &snapshot_field.field_ident;
+            quote::quote! {
+                fn write_from_table_row(&mut self, row: &google_bigquery2::api::TableRow, index_to_name_mapping: &std::collections::HashMap<String, usize>) -> Result<(), google_bigquery::Error> {
+                    self.write_columns_from_table_row(row, index_to_name_mapping, None)
+                }
+                fn write_columns_from_table_row(&mut self,
+                                                row: &google_bigquery2::api::TableRow,
+                                                index_to_name_mapping: &std::collections::HashMap<String, usize>,
+                                                columns: Option<&[String]>)
+                                                -> Result<(), google_bigquery::Error> {
+                    #(#tokens)*
+                    self.#snapshot_ident.set_loaded_fields(columns.map(|columns| columns.to_vec()));
+                    self.#snapshot_ident.set(&self.get_all_query_parameters());
+                    Ok(())
+                }
+            }
         }
+        // without a snapshot there is nowhere to record which fields were loaded,
+        // so only whole rows can be written (see `BigDataTableHasSnapshot`)
+        None => quote::quote! {
+            fn write_from_table_row(&mut self, row: &google_bigquery2::api::TableRow, index_to_name_mapping: &std::collections::HashMap<String, usize>) -> Result<(), google_bigquery::Error> {
+                let columns: Option<&[String]> = None;
+                #(#tokens)*
+                Ok(())
+            }
+        },
     }
 }
 
-fn get_create_from_table_row(pk_ty: &syn::Type) -> proc_macro2::TokenStream {
+fn get_create_from_table_row(table_ident: &proc_macro2::Ident, pk_ty: &syn::Type) -> proc_macro2::TokenStream {
+    let struct_name = table_ident.to_string();
     quote::quote! {
         fn create_from_table_row(client: &'a BigqueryClient,
                                  row: &google_bigquery2::api::TableRow,
                                  index_to_name_mapping: &std::collections::HashMap<String, usize>)
-                                 -> Result<Self, Box<dyn std::error::Error>>
+                                 -> Result<Self, google_bigquery::Error>
             where
                 Self: Sized {
-            //TODO
-            // create_from_table_row maybe push this to the convenience part.
-            // NOTE: its a bit weird with the unwrap and the pk type if not implemented here, but I believe :)
-            let pk_index = *index_to_name_mapping.get(&Self::get_pk_name()).unwrap();
-            let pk = row
-                .f.as_ref()
-                .unwrap()[pk_index]
-                .v.as_ref()
-                .unwrap()
-                .parse::<#pk_ty>()
-                .unwrap();
+            let pk_name = Self::get_pk_name();
+            let pk = match google_bigquery::utils::get_table_row_value(row, index_to_name_mapping, #struct_name, &pk_name)? {
+                Some(v) => <#pk_ty>::from_bigquery_value(v)
+                    .map_err(|e| google_bigquery::Error::Parse {
+                        struct_name: #struct_name.to_string(),
+                        field: pk_name.clone(),
+                        value: Some(v.to_string()),
+                        message: e.to_string(),
+                    })?,
+                None => return Err(google_bigquery::Error::Parse {
+                    struct_name: #struct_name.to_string(),
+                    field: pk_name,
+                    value: None,
+                    message: "the primary key is NULL".to_string(),
+                }),
+            };
             let mut res = Self::create_with_pk(client, pk);
             res.write_from_table_row(row, index_to_name_mapping)?;
             Ok(res)
@@ -316,6 +347,104 @@ fn get_create_with_pk(pk_field: &Field, client_field: &Field) -> proc_macro2::To
     }
 }
 
+fn get_has_snapshot(table_ident: &proc_macro2::Ident, snapshot_field: &Option<Field>) -> proc_macro2::TokenStream {
+    match snapshot_field {
+        Some(_) => quote::quote! {
+            impl<'a> google_bigquery::BigDataTableHasSnapshot for #table_ident<'a> {}
+        },
+        None => quote::quote!(),
+    }
+}
+
+fn get_get_snapshot(snapshot_field: &Option<Field>) -> proc_macro2::TokenStream {
+    match snapshot_field {
+        Some(snapshot_field) => {
+            let snapshot_ident = &snapshot_field.field_ident;
+            quote::quote! {
+                fn get_snapshot(&self) -> Option<&google_bigquery::Snapshot> {
+                    Some(&self.#snapshot_ident)
+                }
+            }
+        }
+        None => quote::quote!(),
+    }
+}
+
+fn get_columns(ast: &syn::DeriveInput, table_ident: &proc_macro2::Ident, db_fields: &[Field]) -> proc_macro2::TokenStream {
+    let vis = &ast.vis;
+    let columns_ident = quote::format_ident!("{}Columns", table_ident);
+    let columns_doc = format!("The columns of [`{}`], see [`{}::COLUMNS`].", table_ident, table_ident);
+    let field_idents: Vec<&proc_macro2::Ident> = db_fields.iter().map(|field| &field.field_ident).collect();
+    let field_names: Vec<String> = db_fields.iter().map(|field| field.local_name.clone()).collect();
+    let field_types: Vec<&syn::Type> = db_fields.iter()
+        .map(|field| extract_type_from_option(&field.ty).unwrap_or(&field.ty))
+        .collect();
+    quote::quote! {
+        #[doc = #columns_doc]
+        #[derive(Debug, Clone, Copy)]
+        #vis struct #columns_ident<'a> {
+            #(pub #field_idents: google_bigquery::Column<#table_ident<'a>, #field_types>,)*
+        }
+        impl<'a> #table_ident<'a> {
+            /// The columns of the fields, to use in filters instead of the names of the fields.
+            pub const COLUMNS: #columns_ident<'a> = #columns_ident {
+                #(#field_idents: google_bigquery::Column::new(#field_names),)*
+            };
+        }
+    }
+}
+
+fn get_struct_parameter(table_ident: &proc_macro2::Ident, db_fields: &[Field]) -> proc_macro2::TokenStream {
+    let db_names: Vec<&String> = db_fields.iter().map(|field| &field.db_name).collect();
+    let field_types: Vec<&syn::Type> = db_fields.iter()
+        .map(|field| extract_type_from_option(&field.ty).unwrap_or(&field.ty))
+        .collect();
+    let parse_error = format!("{} can not be parsed from a query result", table_ident);
+    quote::quote! {
+        impl<'a> google_bigquery::utils::ConvertTypeToBigQueryType for #table_ident<'a> {
+            fn to_bigquery_type() -> String {
+                "STRUCT".to_string()
+            }
+            fn to_bigquery_parameter_type() -> google_bigquery2::api::QueryParameterType {
+                google_bigquery::utils::get_struct_parameter_type(vec![
+                    #((#db_names, <#field_types as google_bigquery::utils::ConvertTypeToBigQueryType>::to_bigquery_parameter_type()),)*
+                ])
+            }
+        }
+        impl<'a> google_bigquery::utils::ConvertValueToBigqueryParamValue for #table_ident<'a> {
+            fn to_bigquery_param_value(&self) -> String {
+                google_bigquery::utils::struct_parameter_value_to_string(&self.to_bigquery_parameter_value())
+            }
+            fn from_bigquery_value(value: &String) -> Result<Self, Box<dyn std::error::Error>> {
+                Err(#parse_error.into())
+            }
+            fn to_bigquery_parameter_value(&self) -> google_bigquery2::api::QueryParameterValue {
+                google_bigquery::utils::get_struct_parameter_value(self.get_all_query_parameters())
+            }
+        }
+    }
+}
+
+fn get_version_functions(version_field: &Option<Field>) -> proc_macro2::TokenStream {
+    match version_field {
+        Some(version_field) => {
+            let version_ident = &version_field.field_ident;
+            let version_name = &version_field.db_name;
+            quote::quote! {
+                fn get_version_name() -> Option<String> {
+                    Some(#version_name.to_string())
+                }
+                fn increment_version(&mut self) -> Option<Box<dyn FnOnce(&mut Self) + Send>> {
+                    let previous = self.#version_ident.clone();
+                    self.#version_ident = google_bigquery::utils::BigDataVersionType::next_version(&self.#version_ident);
+                    Some(Box::new(move |row: &mut Self| row.#version_ident = previous))
+                }
+            }
+        }
+        None => quote::quote!(),
+    }
+}
+
 fn get_get_table_name(table_name: &str) -> proc_macro2::TokenStream {
     quote::quote! {
         fn get_table_name() -> String {
@@ -366,14 +495,17 @@ fn get_get_field_name(ast: &syn::DeriveInput, db_fields: &Vec<Field>) -> proc_ma
         }
     }
     let mapping_tok: Vec<proc_macro2::TokenStream> = db_fields.iter().map(get_field_name_single_field).collect();
-    let possible_fields: String = db_fields.iter().map(|field| field.field_ident.to_string()).collect::<Vec<String>>().join(", ");
+    let possible_fields: Vec<String> = db_fields.iter().map(|field| field.field_ident.to_string()).collect();
     quote::quote! {
-        fn get_field_name(field_name: &str) -> Result<String, Box<dyn std::error::Error>> {
+        fn get_field_name(field_name: &str) -> Result<String, google_bigquery::Error> {
             // println!("get_field_name: field_name: {:?}", field_name);
             match field_name {
                 //ex.: "row_id" => Ok("Id".to_string()),
                 #(#mapping_tok)*
-                _ => Err(format!("Field not found {}\nPlease choose one of the following: {}", field_name, #possible_fields).into()),
+                _ => Err(google_bigquery::Error::UnknownField {
+                    field: field_name.to_string(),
+                    available_fields: vec![#(#possible_fields.to_string()),*],
+                }),
             }
         }
     }
@@ -411,6 +543,22 @@ fn get_client_field(ast: &syn::DeriveInput) -> Field {
     client
 }
 
+fn get_snapshot_field(ast: &syn::DeriveInput) -> Option<Field> {
+    let mut snapshot_fields = get_attributed_fields(&ast.data, "snapshot");
+    if snapshot_fields.len() > 1 {
+        panic!("At most one snapshot field can be specified");
+    }
+    snapshot_fields.pop()
+}
+
+fn get_version_field(ast: &syn::DeriveInput) -> Option<Field> {
+    let mut version_fields = get_attributed_fields(&ast.data, "version");
+    if version_fields.len() > 1 {
+        panic!("At most one version field can be specified");
+    }
+    version_fields.pop()
+}
+
 fn get_struct_attributes(ast: &syn::DeriveInput) -> Vec<Attribute> {
     let attrs = &ast.attrs;
     let mut res = vec![];
@@ -494,7 +642,8 @@ fn get_fields(data: &syn::Data) -> Vec<Field> {
                                 let args = args.value();
                                 name = Some(args);
                             }
-                            if attribute.path.is_ident("required") {
+                            if attribute.path.is_ident("required") || attribute.path.is_ident("version") {
+                                // the version can not be NULL
                                 required = true;
                             }
                         }
@@ -1,18 +1,39 @@
-use std::error::Error;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
 use std::fmt::{Debug, Display, Formatter};
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
+use futures::{Stream, TryStreamExt};
 use google_bigquery2::{Bigquery, hyper, hyper_rustls, oauth2};
+use google_bigquery2::api::{DatasetReference, JobReference, QueryParameter, QueryRequest, QueryResponse};
 use google_bigquery2::hyper::client::HttpConnector;
 use google_bigquery2::hyper_rustls::HttpsConnector;
 
+use crate::credentials::Credentials;
+use crate::error::Error;
+use crate::executor::{GetQueryResultsRequest, QueryExecutor};
 use crate::googlebigquery;
+use crate::query_result::QueryResult;
+use crate::retry::RetryPolicy;
+use crate::testing::{RecordingExecutor, ReplayExecutor};
 
 pub struct BigqueryClient {
-    client: Bigquery<HttpsConnector<HttpConnector>>,
+    executor: Box<dyn QueryExecutor>,
     project_id: String,
     dataset_id: String,
+    request_timeout: Option<Duration>,
+    wait_timeout: Option<Duration>,
+    query_options: QueryOptions,
+    retry_policy: RetryPolicy,
+    save_mode: SaveMode,
 }
 
+/// The longest time a single `jobs.getQueryResults` call waits for a job to complete.
+const MAX_POLL_TIMEOUT: Duration = Duration::from_secs(10);
+
 impl BigqueryClient {
     pub(crate) fn empty() -> &'static BigqueryClient {
         todo!("Implement BigqueryClient::empty() or throw an error if it's not possible or something.");
@@ -36,21 +57,50 @@ impl BigqueryClient {
 }
 
 impl BigqueryClient {
+    pub fn builder() -> BigqueryClientBuilder {
+        BigqueryClientBuilder::default()
+    }
+
     pub async fn new<S: Into<String>>(
         project_id: S,
         dataset_id: S,
-        service_account_path: Option<S>,
-    ) -> Result<BigqueryClient, Box<dyn Error>> {
-        let client = googlebigquery::get_client(service_account_path).await?;
-        Ok(BigqueryClient {
-            client,
-            project_id: project_id.into(),
-            dataset_id: dataset_id.into(),
-        })
+        credentials: Credentials,
+    ) -> Result<BigqueryClient, Error> {
+        Self::builder()
+            .project_id(project_id)
+            .dataset_id(dataset_id)
+            .credentials(credentials)
+            .build()
+            .await
     }
 
-    pub fn get_client(&self) -> &Bigquery<HttpsConnector<HttpConnector>> {
-        &self.client
+    /// Creates a client that sends its requests to `root_url` instead of `https://bigquery.googleapis.com/`.
+    pub async fn new_with_root_url<S: Into<String>>(
+        project_id: S,
+        dataset_id: S,
+        credentials: Credentials,
+        root_url: S,
+    ) -> Result<BigqueryClient, Error> {
+        Self::builder()
+            .project_id(project_id)
+            .dataset_id(dataset_id)
+            .credentials(credentials)
+            .root_url(root_url)
+            .build()
+            .await
+    }
+
+    /// Creates an unauthenticated client for a local emulator (e.g. bigquery-emulator) listening on `root_url`.
+    pub async fn new_emulator<S: Into<String>>(
+        project_id: S,
+        dataset_id: S,
+        root_url: S,
+    ) -> Result<BigqueryClient, Error> {
+        Self::new_with_root_url(project_id, dataset_id, Credentials::Unauthenticated, root_url).await
+    }
+
+    pub fn get_executor(&self) -> &dyn QueryExecutor {
+        self.executor.as_ref()
     }
     pub fn get_project_id(&self) -> &str {
         &self.project_id
@@ -58,6 +108,380 @@ impl BigqueryClient {
     pub fn get_dataset_id(&self) -> &str {
         &self.dataset_id
     }
+    pub fn get_request_timeout(&self) -> Option<Duration> {
+        self.request_timeout
+    }
+    pub fn get_wait_timeout(&self) -> Option<Duration> {
+        self.wait_timeout
+    }
+    pub fn get_query_options(&self) -> &QueryOptions {
+        &self.query_options
+    }
+    pub fn get_retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+    pub fn get_save_mode(&self) -> SaveMode {
+        self.save_mode
+    }
+
+    /// Runs the query with the client defaults applied, retrying transient
+    /// errors according to the retry policy.
+    ///
+    /// Waits for the job to complete and reads all pages of the result,
+    /// so the returned response contains every row.
+    pub async fn run_query(&self, req: QueryRequest, project_id: &str)
+                           -> Result<QueryResponse, Error> {
+        let pages = self.run_query_pages(req, project_id);
+        futures::pin_mut!(pages);
+        let mut response = pages.try_next().await?
+            .ok_or_else(|| Error::InvalidResponse("The query returned no response".to_string()))?;
+        while let Some(page) = pages.try_next().await? {
+            merge_query_results(&mut response, page);
+        }
+        Ok(response)
+    }
+
+    /// Runs the SQL query with the parameters (see [`get_query_param`](crate::utils::get_query_param))
+    /// and returns all of its rows, for queries whose rows are not the ones of a table.
+    pub async fn query(&self, sql: &str, params: Vec<QueryParameter>) -> Result<QueryResult, Error> {
+        let req = QueryRequest {
+            query: Some(sql.to_string()),
+            query_parameters: Some(params),
+            use_legacy_sql: Some(false),
+            ..Default::default()
+        };
+        let response = self.run_query(req, self.get_project_id()).await?;
+        QueryResult::from_response(response)
+    }
+
+    /// Runs the query like [`run_query`](Self::run_query), but returns the result one page at a time.
+    ///
+    /// A page is only requested once the stream is polled for it. Incomplete responses are not
+    /// returned, the stream waits for the job to complete instead.
+    ///
+    /// Requests without a `request_id` get a random one. A retry after a lost response (a transport error
+    /// or a timeout) sends the same id again, so BigQuery runs the statement only once. A retry after
+    /// BigQuery answered with an error gets a new id, since BigQuery would answer the old one with the same failed job.
+    pub fn run_query_pages<'c>(&'c self, mut req: QueryRequest, project_id: &'c str)
+                               -> impl Stream<Item=Result<QueryResponse, Error>> + Send + 'c {
+        self.apply_query_options(&mut req);
+        if req.request_id.is_none() {
+            req.request_id = Some(new_request_id());
+        }
+        futures::stream::try_unfold(NextPage::Query(Box::new(req)), move |next| self.fetch_page(next, project_id))
+    }
+
+    /// Sends the request for the page and polls `jobs.getQueryResults` until the job of it is complete.
+    async fn fetch_page(&self, next: NextPage, project_id: &str) -> Result<Option<(QueryResponse, NextPage)>, Error> {
+        let started = Instant::now();
+        let mut response = match next {
+            NextPage::Query(mut req) => self.with_retries(|previous_error| {
+                if previous_error.is_some_and(|e| !e.is_transport_error()) {
+                    req.request_id = Some(new_request_id());
+                }
+                self.executor.query((*req).clone(), project_id)
+            }).await?,
+            NextPage::Results(req) => self.get_query_results(req, project_id).await?,
+            NextPage::Done => return Ok(None),
+        };
+        while !response.job_complete.unwrap_or(true) {
+            let mut timeout = MAX_POLL_TIMEOUT;
+            if let Some(wait_timeout) = self.wait_timeout {
+                let remaining = wait_timeout.saturating_sub(started.elapsed());
+                if remaining.is_zero() {
+                    let job_id = get_results_request(&response, None, timeout)?.job_id;
+                    return Err(Error::JobTimeout { job_id, waited: started.elapsed() });
+                }
+                timeout = timeout.min(remaining);
+            }
+            let req = get_results_request(&response, None, timeout)?;
+            response = self.get_query_results(req, project_id).await?;
+        }
+        let next = match response.page_token.take() {
+            Some(page_token) => NextPage::Results(get_results_request(&response, Some(page_token), MAX_POLL_TIMEOUT)?),
+            None => NextPage::Done,
+        };
+        Ok(Some((response, next)))
+    }
+
+    /// Calls `jobs.getQueryResults` and returns the answer in the shape of a query response.
+    async fn get_query_results(&self, req: GetQueryResultsRequest, project_id: &str) -> Result<QueryResponse, Error> {
+        let results = self.with_retries(|_| self.executor.get_query_results(req.clone(), project_id)).await?;
+        Ok(QueryResponse {
+            cache_hit: results.cache_hit,
+            errors: results.errors,
+            job_complete: results.job_complete,
+            job_reference: results.job_reference.or(Some(JobReference {
+                job_id: Some(req.job_id),
+                location: req.location,
+                project_id: Some(project_id.to_string()),
+            })),
+            kind: results.kind,
+            num_dml_affected_rows: results.num_dml_affected_rows,
+            page_token: results.page_token,
+            rows: results.rows,
+            schema: results.schema,
+            total_bytes_processed: results.total_bytes_processed,
+            total_rows: results.total_rows,
+            ..Default::default()
+        })
+    }
+
+    /// Runs the request (with the request timeout) until it succeeds, fails with an error
+    /// that is not retryable or the retry policy gives up.
+    ///
+    /// `request` gets the error of the previous attempt, `None` for the first one.
+    async fn with_retries<T, F, Fut>(&self, mut request: F) -> Result<T, Error>
+        where F: FnMut(Option<&Error>) -> Fut,
+              Fut: Future<Output=Result<T, Error>> {
+        let mut attempt = 1;
+        let mut previous_error = None;
+        loop {
+            let query = request(previous_error.as_ref());
+            let result = match self.request_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, query).await
+                    .unwrap_or_else(|_| Err(Error::Request(Box::new(google_bigquery2::Error::Io(
+                        std::io::Error::new(std::io::ErrorKind::TimedOut, format!("Request timed out after {:?}", timeout))))))),
+                None => query.await,
+            };
+            let e = match result {
+                Ok(query_res) => return Ok(query_res),
+                Err(e) => e,
+            };
+            if attempt >= self.retry_policy.max_attempts || !self.retry_policy.is_retryable(&e) {
+                return Err(e);
+            }
+            tokio::time::sleep(self.retry_policy.get_backoff(attempt)).await;
+            previous_error = Some(e);
+            attempt += 1;
+        }
+    }
+
+    /// Fills in the client defaults for everything the request does not set itself.
+    pub(crate) fn apply_query_options(&self, req: &mut QueryRequest) {
+        if req.default_dataset.is_none() {
+            req.default_dataset = Some(DatasetReference {
+                project_id: Some(self.project_id.clone()),
+                dataset_id: Some(self.dataset_id.clone()),
+            });
+        }
+        if req.location.is_none() {
+            req.location = self.query_options.location.clone();
+        }
+        if req.use_query_cache.is_none() {
+            req.use_query_cache = self.query_options.use_query_cache;
+        }
+        if !self.query_options.labels.is_empty() {
+            let mut labels = self.query_options.labels.clone();
+            labels.extend(req.labels.take().unwrap_or_default());
+            req.labels = Some(labels);
+        }
+    }
+}
+
+/// A random id in the format of a (version 4) UUID.
+fn new_request_id() -> String {
+    let random = |salt: u64| {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(salt);
+        hasher.finish()
+    };
+    let high = (random(1) & 0xffff_ffff_ffff_0fff) | 0x4000;
+    let low = (random(2) & 0x3fff_ffff_ffff_ffff) | 0x8000_0000_0000_0000;
+    format!("{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+            high >> 32, (high >> 16) & 0xffff, high & 0xffff, low >> 48, low & 0xffff_ffff_ffff)
+}
+
+/// Where the next page of a query result comes from.
+enum NextPage {
+    Query(Box<QueryRequest>),
+    Results(GetQueryResultsRequest),
+    Done,
+}
+
+/// The request for the results of the job of the response.
+fn get_results_request(response: &QueryResponse, page_token: Option<String>, timeout: Duration) -> Result<GetQueryResultsRequest, Error> {
+    let job_reference = response.job_reference.clone().unwrap_or_default();
+    let job_id = job_reference.job_id
+        .ok_or_else(|| Error::InvalidResponse("The query has more results, but the response has no job id".to_string()))?;
+    Ok(GetQueryResultsRequest {
+        job_id,
+        location: job_reference.location,
+        page_token,
+        timeout_ms: Some(timeout.as_millis() as u32),
+    })
+}
+
+/// Adds a page of results to the response and takes over the state of the job.
+fn merge_query_results(response: &mut QueryResponse, page: QueryResponse) {
+    response.job_complete = page.job_complete;
+    response.page_token = page.page_token;
+    if page.schema.is_some() {
+        response.schema = page.schema;
+    }
+    if page.total_rows.is_some() {
+        response.total_rows = page.total_rows;
+    }
+    if page.num_dml_affected_rows.is_some() {
+        response.num_dml_affected_rows = page.num_dml_affected_rows;
+    }
+    if page.total_bytes_processed.is_some() {
+        response.total_bytes_processed = page.total_bytes_processed;
+    }
+    if let Some(rows) = page.rows {
+        response.rows.get_or_insert_with(Vec::new).extend(rows);
+    }
+}
+
+/// How [`save_to_bigquery`](crate::BigDataTable::save_to_bigquery) writes a row.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SaveMode {
+    /// Counts the rows with the primary key first and then runs an `INSERT` or an `UPDATE` (two jobs).
+    /// Fails if the primary key is not unique.
+    #[default]
+    CheckThenWrite,
+    /// Runs a single `MERGE` on the primary key, which updates or inserts the row atomically.
+    Merge,
+}
+
+/// Settings that are applied to every query the client runs, unless the request sets them itself.
+#[derive(Debug, Clone, Default)]
+pub struct QueryOptions {
+    /// The location/region the jobs run in (e.g. `EU` or `us-central1`).
+    pub location: Option<String>,
+    /// Labels attached to every job. Labels set on the request take precedence.
+    pub labels: HashMap<String, String>,
+    pub use_query_cache: Option<bool>,
+}
+
+#[derive(Default)]
+pub struct BigqueryClientBuilder {
+    project_id: Option<String>,
+    dataset_id: Option<String>,
+    credentials: Option<Credentials>,
+    root_url: Option<String>,
+    user_agent: Option<String>,
+    request_timeout: Option<Duration>,
+    wait_timeout: Option<Duration>,
+    query_options: QueryOptions,
+    retry_policy: Option<RetryPolicy>,
+    save_mode: SaveMode,
+    executor: Option<Box<dyn QueryExecutor>>,
+    record_to: Option<PathBuf>,
+    replay_from: Option<PathBuf>,
+}
+
+impl BigqueryClientBuilder {
+    pub fn project_id(mut self, project_id: impl Into<String>) -> Self {
+        self.project_id = Some(project_id.into());
+        self
+    }
+    /// The dataset the tables are looked up in.
+    pub fn dataset_id(mut self, dataset_id: impl Into<String>) -> Self {
+        self.dataset_id = Some(dataset_id.into());
+        self
+    }
+    /// Defaults to [`Credentials::ApplicationDefault`].
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+    /// Sends all requests to `root_url` instead of `https://bigquery.googleapis.com/`.
+    pub fn root_url(mut self, root_url: impl Into<String>) -> Self {
+        self.root_url = Some(root_url.into());
+        self
+    }
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+    /// The maximum time a single request to the server may take.
+    pub fn request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = Some(request_timeout);
+        self
+    }
+    /// The maximum time to wait for a query job to complete. Waits as long as it takes if not set.
+    pub fn wait_timeout(mut self, wait_timeout: Duration) -> Self {
+        self.wait_timeout = Some(wait_timeout);
+        self
+    }
+    pub fn location(mut self, location: impl Into<String>) -> Self {
+        self.query_options.location = Some(location.into());
+        self
+    }
+    pub fn label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.query_options.labels.insert(key.into(), value.into());
+        self
+    }
+    pub fn labels(mut self, labels: HashMap<String, String>) -> Self {
+        self.query_options.labels.extend(labels);
+        self
+    }
+    pub fn use_query_cache(mut self, use_query_cache: bool) -> Self {
+        self.query_options.use_query_cache = Some(use_query_cache);
+        self
+    }
+    /// Defaults to [`RetryPolicy::default`]. Use [`RetryPolicy::none`] to disable retries.
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(retry_policy);
+        self
+    }
+    /// How [`save_to_bigquery`](crate::BigDataTable::save_to_bigquery) writes rows.
+    /// Defaults to [`SaveMode::CheckThenWrite`].
+    pub fn save_mode(mut self, save_mode: SaveMode) -> Self {
+        self.save_mode = save_mode;
+        self
+    }
+    /// Runs all queries through `executor` instead of the BigQuery API.
+    ///
+    /// When set, the credentials, root url and user agent are not used.
+    pub fn executor(mut self, executor: impl QueryExecutor + 'static) -> Self {
+        self.executor = Some(Box::new(executor));
+        self
+    }
+    /// Writes every request and its response to the fixture file at `path`
+    /// (see [`RecordingExecutor`]), so it can be replayed later with [`replay_from`](Self::replay_from).
+    pub fn record_to(mut self, path: impl Into<PathBuf>) -> Self {
+        self.record_to = Some(path.into());
+        self
+    }
+    /// Answers all queries from the fixture file at `path` (see [`ReplayExecutor`])
+    /// instead of the BigQuery API.
+    pub fn replay_from(mut self, path: impl Into<PathBuf>) -> Self {
+        self.replay_from = Some(path.into());
+        self
+    }
+
+    pub async fn build(self) -> Result<BigqueryClient, Error> {
+        let project_id = self.project_id
+            .ok_or_else(|| Error::Other("No project id was set on the BigqueryClientBuilder".to_string()))?;
+        let dataset_id = self.dataset_id
+            .ok_or_else(|| Error::Other("No dataset id was set on the BigqueryClientBuilder".to_string()))?;
+        if self.record_to.is_some() && self.replay_from.is_some() {
+            return Err(Error::Other("Cannot record and replay fixtures at the same time".to_string()));
+        }
+        let mut executor: Box<dyn QueryExecutor> = match (self.executor, self.replay_from) {
+            (_, Some(replay_from)) => Box::new(ReplayExecutor::from_file(replay_from)?),
+            (Some(executor), None) => executor,
+            (None, None) => {
+                let credentials = self.credentials.unwrap_or(Credentials::ApplicationDefault);
+                Box::new(googlebigquery::get_client(credentials, self.root_url, self.user_agent).await?)
+            }
+        };
+        if let Some(record_to) = self.record_to {
+            executor = Box::new(RecordingExecutor::new(executor, record_to));
+        }
+        Ok(BigqueryClient {
+            executor,
+            project_id,
+            dataset_id,
+            request_timeout: self.request_timeout,
+            wait_timeout: self.wait_timeout,
+            query_options: self.query_options,
+            retry_policy: self.retry_policy.unwrap_or_default(),
+            save_mode: self.save_mode,
+        })
+    }
 }
 
 impl Debug for BigqueryClient {
@@ -65,6 +489,11 @@ impl Debug for BigqueryClient {
         f.debug_struct("BigqueryClient")
             .field("project_id", &self.project_id)
             .field("dataset_id", &self.dataset_id)
+            .field("request_timeout", &self.request_timeout)
+            .field("wait_timeout", &self.wait_timeout)
+            .field("query_options", &self.query_options)
+            .field("retry_policy", &self.retry_policy)
+            .field("save_mode", &self.save_mode)
             .finish()
     }
 }
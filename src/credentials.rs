@@ -0,0 +1,152 @@
+use std::fmt::{Debug, Formatter};
+
+use async_trait::async_trait;
+use google_bigquery2::oauth2;
+use google_bigquery2::oauth2::authenticator::{ApplicationDefaultCredentialsTypes, Authenticator};
+use google_bigquery2::oauth2::authorized_user::AuthorizedUserSecret;
+use google_bigquery2::oauth2::storage::{TokenInfo, TokenStorage};
+use google_bigquery2::hyper::client::HttpConnector;
+use google_bigquery2::hyper_rustls::HttpsConnector;
+
+use crate::error::Error;
+
+/// The source the [`BigqueryClient`](crate::BigqueryClient) gets its access tokens from.
+///
+/// The `Debug` output does not contain the keys and tokens, only the paths to them.
+#[derive(Clone)]
+pub enum Credentials {
+    /// Application Default Credentials: the service account key referenced by
+    /// `GOOGLE_APPLICATION_CREDENTIALS` if it is set, the GCE metadata server otherwise.
+    ApplicationDefault,
+    /// The service account key file referenced by `GOOGLE_APPLICATION_CREDENTIALS`.
+    EnvironmentVariable,
+    /// A service account key file at the given path.
+    ServiceAccountFile(String),
+    /// The content of a service account key file (JSON).
+    ServiceAccountKey(String),
+    /// User credentials obtained through the installed (browser) flow.
+    InstalledFlow {
+        /// Path to the client secret JSON downloaded from the cloud console.
+        client_secret_path: String,
+        /// If set, the obtained tokens are persisted to this file and reused on the next start.
+        token_cache_path: Option<String>,
+    },
+    /// A fixed bearer token that is sent as is and never refreshed. Mostly useful for tests.
+    StaticToken(String),
+    /// No credentials at all, for servers that do not check them (e.g. a local emulator).
+    ///
+    /// A dummy bearer token is still sent, since every request carries an authorization header.
+    Unauthenticated,
+}
+
+impl Debug for Credentials {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        const REDACTED: &str = "<redacted>";
+        match self {
+            Credentials::ApplicationDefault => f.write_str("ApplicationDefault"),
+            Credentials::EnvironmentVariable => f.write_str("EnvironmentVariable"),
+            Credentials::ServiceAccountFile(path) => f.debug_tuple("ServiceAccountFile").field(path).finish(),
+            Credentials::ServiceAccountKey(_) => f.debug_tuple("ServiceAccountKey").field(&REDACTED).finish(),
+            Credentials::InstalledFlow { client_secret_path, token_cache_path } => f.debug_struct("InstalledFlow")
+                .field("client_secret_path", client_secret_path)
+                .field("token_cache_path", token_cache_path)
+                .finish(),
+            Credentials::StaticToken(_) => f.debug_tuple("StaticToken").field(&REDACTED).finish(),
+            Credentials::Unauthenticated => f.write_str("Unauthenticated"),
+        }
+    }
+}
+
+pub(crate) async fn get_authenticator(credentials: Credentials)
+                                      -> Result<Authenticator<HttpsConnector<HttpConnector>>, Error> {
+    let auth = match credentials {
+        Credentials::ApplicationDefault => {
+            let opts = oauth2::ApplicationDefaultCredentialsFlowOpts::default();
+            match oauth2::ApplicationDefaultCredentialsAuthenticator::builder(opts).await {
+                ApplicationDefaultCredentialsTypes::ServiceAccount(builder) => builder.build().await,
+                ApplicationDefaultCredentialsTypes::InstanceMetadata(builder) => builder.build().await,
+            }.map_err(|e| Error::Auth(format!("Failed to create application default credentials authenticator: {}", e)))?
+        }
+        Credentials::EnvironmentVariable => {
+            let path = std::env::var("GOOGLE_APPLICATION_CREDENTIALS")
+                .map_err(|e| Error::Auth(format!("Could not read GOOGLE_APPLICATION_CREDENTIALS: {}", e)))?;
+            get_service_account_authenticator_from_file(&path).await?
+        }
+        Credentials::ServiceAccountFile(path) => get_service_account_authenticator_from_file(&path).await?,
+        Credentials::ServiceAccountKey(key) => {
+            let secret = oauth2::parse_service_account_key(key)
+                .map_err(|e| Error::Auth(format!("Failed to parse service account key: {}", e)))?;
+            oauth2::ServiceAccountAuthenticator::builder(secret)
+                .build()
+                .await
+                .map_err(|e| Error::Auth(format!("Failed to authenticate with service account key: {}", e)))?
+        }
+        Credentials::InstalledFlow { client_secret_path, token_cache_path } => {
+            let secret = oauth2::read_application_secret(&client_secret_path)
+                .await
+                .map_err(|e| Error::Auth(format!("Failed to read client secret from file. {}: {}", client_secret_path, e)))?;
+            let mut builder = oauth2::InstalledFlowAuthenticator::builder(secret, oauth2::InstalledFlowReturnMethod::HTTPRedirect);
+            if let Some(token_cache_path) = token_cache_path {
+                builder = builder.persist_tokens_to_disk(token_cache_path);
+            }
+            builder.build()
+                .await
+                .map_err(|e| Error::Auth(format!("Failed to create installed flow authenticator: {}", e)))?
+        }
+        Credentials::StaticToken(token) => get_static_token_authenticator(token).await?,
+        Credentials::Unauthenticated => get_static_token_authenticator("unauthenticated".to_string()).await?,
+    };
+    Ok(auth)
+}
+
+async fn get_service_account_authenticator_from_file(path: &str)
+                                                     -> Result<Authenticator<HttpsConnector<HttpConnector>>, Error> {
+    let secret = oauth2::read_service_account_key(path)
+        .await
+        .map_err(|e| Error::Auth(format!("Failed to read service account key from file. {}: {}", path, e)))?;
+    let auth = oauth2::ServiceAccountAuthenticator::builder(secret)
+        .build()
+        .await
+        .map_err(|e| Error::Auth(format!("Failed to authenticate with service account key: {}", e)))?;
+    Ok(auth)
+}
+
+/// Builds an authenticator that always hands out the given token.
+///
+/// The authorized user flow is never run, since the storage always returns a token
+/// without expiry, so its secret does not have to be valid.
+async fn get_static_token_authenticator(token: String)
+                                        -> Result<Authenticator<HttpsConnector<HttpConnector>>, Error> {
+    let secret = AuthorizedUserSecret {
+        client_id: String::new(),
+        client_secret: String::new(),
+        refresh_token: String::new(),
+        key_type: "authorized_user".to_string(),
+    };
+    let auth = oauth2::AuthorizedUserAuthenticator::builder(secret)
+        .with_storage(Box::new(StaticTokenStorage { token }))
+        .build()
+        .await
+        .map_err(|e| Error::Auth(format!("Failed to create static token authenticator: {}", e)))?;
+    Ok(auth)
+}
+
+struct StaticTokenStorage {
+    token: String,
+}
+
+#[async_trait]
+impl TokenStorage for StaticTokenStorage {
+    async fn set(&self, _scopes: &[&str], _token: TokenInfo) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn get(&self, _scopes: &[&str]) -> Option<TokenInfo> {
+        Some(TokenInfo {
+            access_token: self.token.clone(),
+            refresh_token: None,
+            expires_at: None,
+            id_token: None,
+        })
+    }
+}
@@ -1,10 +1,42 @@
 use std::collections::HashMap;
-use std::error::Error;
 use std::str::FromStr;
 
 use crate::client::{BigqueryClient, HasBigQueryClient};
+use crate::data::Snapshot;
+use crate::error::Error;
 use crate::utils::BigDataValueType;
 
+/// Restores the previous version of a row after [`increment_version`](BigDataTableBase::increment_version).
+pub type RestoreVersion<TABLE> = Box<dyn FnOnce(&mut TABLE) + Send>;
+
+/// Implemented by the derive for structs with a `#[snapshot]` field, which records the fields a row was
+/// loaded with. Only rows of such structs can be loaded with some of their fields, since saving
+/// a row without that record would overwrite the fields that were not loaded with their defaults.
+///
+/// ```compile_fail
+/// # use google_bigquery::{BigDataTable, BigDataTableDerive, BigqueryClient, HasBigQueryClientDerive};
+/// # use google_bigquery::{BigDataTableBase, BigDataTableBaseConvenience, BigDataTableHasPk, HasBigQueryClient};
+/// # use google_bigquery::utils::ConvertValueToBigqueryParamValue;
+/// #[derive(Default, HasBigQueryClientDerive, BigDataTableDerive)]
+/// struct Infos<'a> {
+///     #[primary_key]
+///     #[required]
+///     id: String,
+///     #[client]
+///     client: Option<&'a BigqueryClient>,
+///     info: Option<String>,
+/// }
+///
+/// async fn load(client: &BigqueryClient) {
+///     Infos::load_by_field_with_columns(client, "id", Some("1".to_string()), &["info"], 10).await;
+/// }
+/// ```
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can not load only some of its fields",
+    note = "add a `#[snapshot]` field to record which fields a row was loaded with"
+)]
+pub trait BigDataTableHasSnapshot {}
+
 pub trait BigDataTableHasPk<TPK>
     where TPK: BigDataValueType<TPK> + FromStr + std::fmt::Debug + Clone {
     fn get_pk_name() -> String;
@@ -16,23 +48,53 @@ pub trait BigDataTableBase<'a, TABLE, TPK>: HasBigQueryClient<'a> + BigDataTable
 {
     // fn get_pk_name() -> String;
     // fn get_pk_value(&self) -> TPK;
-    fn get_field_name(field_name: &str) -> Result<String, Box<dyn Error>>;
+    fn get_field_name(field_name: &str) -> Result<String, Error>;
     fn get_query_fields() -> HashMap<String, String>;
     fn get_table_name() -> String;
     fn create_with_pk(client: &'a BigqueryClient, pk: TPK) -> TABLE;
     fn write_from_table_row(&mut self,
                             row: &google_bigquery2::api::TableRow,
                             index_to_name_mapping: &HashMap<String, usize>)
-                            -> Result<(), Box<dyn Error>>;
+                            -> Result<(), Error>;
+    /// Like [`write_from_table_row`](Self::write_from_table_row), but only writes the fields of the `columns`
+    /// (all of them for `None`) and leaves the other ones as they are.
+    fn write_columns_from_table_row(&mut self,
+                                    row: &google_bigquery2::api::TableRow,
+                                    index_to_name_mapping: &HashMap<String, usize>,
+                                    columns: Option<&[String]>)
+                                    -> Result<(), Error> {
+        match columns {
+            None => self.write_from_table_row(row, index_to_name_mapping),
+            Some(_) => Err(Error::Other(format!("{} can not load only some of its fields", Self::get_table_name()))),
+        }
+    }
     // fn get_query_fields_update_str(&self) -> String;
     fn get_all_query_parameters(&self) -> Vec<google_bigquery2::api::QueryParameter>;
+    /// The snapshot of the `#[snapshot]` field, `None` if the struct has no such field.
+    fn get_snapshot(&self) -> Option<&Snapshot> {
+        None
+    }
+    /// The columns that were loaded, `None` if all of them were (see [`Snapshot::get_loaded_fields`]).
+    /// Rows of structs without a `#[snapshot]` field are always loaded with all of their columns.
+    fn get_loaded_fields(&self) -> Option<Vec<String>> {
+        self.get_snapshot()?.get_loaded_fields()
+    }
+    /// The column of the `#[version]` field, `None` if the struct has no such field.
+    fn get_version_name() -> Option<String> {
+        None
+    }
+    /// Sets the `#[version]` field to its next version and returns a function that restores
+    /// the previous one, `None` if the struct has no such field.
+    fn increment_version(&mut self) -> Option<RestoreVersion<Self>> {
+        None
+    }
 
     fn create_from_table_row(client: &'a BigqueryClient,
                              row: &google_bigquery2::api::TableRow,
                              index_to_name_mapping: &HashMap<String, usize>)
-                             -> Result<Self, Box<dyn Error>>
+                             -> Result<Self, Error>
         where
             Self: Sized;
 
-    // fn parse_bigquery_value<T: BigDataValueType<T>>(value: &String) -> Result<T, Box<dyn Error>>;
+    // fn parse_bigquery_value<T: BigDataValueType<T>>(value: &String) -> Result<T, Error>;
 }
@@ -1,14 +1,13 @@
 use std::collections::HashMap;
-use std::error::Error;
 use std::fmt::Debug;
 use std::str::FromStr;
 use async_trait::async_trait;
 
 use google_bigquery2::api::{QueryParameter, QueryParameterType, QueryParameterValue, QueryRequest};
-use google_bigquery2::hyper::{Body, Response};
 
 use crate::client::BigqueryClient;
 use crate::data::BigDataTableBase;
+use crate::error::Error;
 use crate::utils::BigDataValueType;
 
 #[async_trait]
@@ -19,44 +18,69 @@ pub trait BigDataTableBaseConvenience<'a, TABLE, TPK>
     fn get_pk_param(&self) -> google_bigquery2::api::QueryParameter;
     fn get_query_fields_str() -> String;
     fn get_query_fields_insert_str() -> String;
+    /// The fields an update has to set: the ones that changed since the snapshot
+    /// (see [`Snapshot`](crate::Snapshot)), or all of them except the primary key.
+    fn get_update_fields(&self) -> Vec<String>;
     fn get_query_fields_update_str(&self) -> String;
+    /// The parameters of the [update fields](Self::get_update_fields) and the primary key.
+    fn get_update_query_parameters(&self) -> Vec<QueryParameter>;
+    /// A `MERGE` on the primary key that inserts the rows of `source` that do not exist
+    /// (see [`get_merge_source_str`](Self::get_merge_source_str)) and sets the `update_fields` of the other ones.
+    /// Rows are only updated if they also match `matched_condition`.
+    fn get_merge_query(table_identifier: &str, source: &str, update_fields: &[String], matched_condition: Option<&str>) -> String;
+    /// A select of the query parameters of one row as columns, for the source of a `MERGE`.
+    /// `suffix` is appended to the parameter names.
+    fn get_merge_source_str(suffix: &str) -> String;
     fn get_where_part(field_name: &str, is_comparing_to_null: bool) -> String;
+    /// The condition that the `#[version]` column (qualified with `table_alias` if it is not empty)
+    /// still has the current version of the row, and the parameter of that version.
+    /// `None` if the struct has no version field.
+    fn get_version_check(&self, table_alias: &str) -> Option<(String, QueryParameter)>;
+    /// The query and parameters that select the rows where the field has the value (or is NULL),
+    /// up to `max_amount` rows if it is set. Selects only the `columns` if they are set.
+    async fn get_load_by_field_query<T: BigDataValueType<T> + Send>(client: &'a BigqueryClient,
+                                                                    field_name: &str,
+                                                                    field_value: Option<T>,
+                                                                    columns: Option<&[String]>,
+                                                                    max_amount: Option<usize>)
+                                                                    -> Result<(String, Vec<QueryParameter>), Error>
+        where TABLE: 'async_trait;
     //region run query
     async fn run_query(&self, req: QueryRequest, project_id: &str)
-                       -> Result<(Response<Body>, google_bigquery2::api::QueryResponse), Box<dyn Error>>;
+                       -> Result<google_bigquery2::api::QueryResponse, Error>;
 
     async fn run_query_on_client(client: &'a BigqueryClient,
                                  req: QueryRequest,
                                  project_id: &str)
-                                 -> Result<(Response<Body>, google_bigquery2::api::QueryResponse), Box<dyn Error>>;
+                                 -> Result<google_bigquery2::api::QueryResponse, Error>;
     //endregion run query
 
     //region run get query
     async fn run_get_query(&self, query: &str, project_id: &str)
-                           -> Result<google_bigquery2::api::QueryResponse, Box<dyn Error>>;
+                           -> Result<google_bigquery2::api::QueryResponse, Error>;
 
     async fn run_get_query_with_params(&self,
                                        query: &str,
                                        parameters: Vec<google_bigquery2::api::QueryParameter>,
                                        project_id: &str)
-                                       -> Result<google_bigquery2::api::QueryResponse, Box<dyn Error>>;
+                                       -> Result<google_bigquery2::api::QueryResponse, Error>;
 
     async fn run_get_query_with_params_on_client(client: &'a BigqueryClient,
                                                  query: &str,
                                                  parameters: Vec<google_bigquery2::api::QueryParameter>,
                                                  project_id: &str)
-                                                 -> Result<google_bigquery2::api::QueryResponse, Box<dyn Error>>
+                                                 -> Result<google_bigquery2::api::QueryResponse, Error>
         where TABLE: 'async_trait;
     //endregion
 
 
-    // async fn get_identifier_and_base_where(&self) -> Result<(String, String), Box<dyn Error>>;
-    async fn get_identifier(&self) -> Result<String, Box<dyn Error>>;
-    async fn get_identifier_from_client(client: &'a BigqueryClient) -> Result<String, Box<dyn Error>>
+    // async fn get_identifier_and_base_where(&self) -> Result<(String, String), Error>;
+    async fn get_identifier(&self) -> Result<String, Error>;
+    async fn get_identifier_from_client(client: &'a BigqueryClient) -> Result<String, Error>
         where TABLE: 'async_trait;
     fn get_base_where() -> String;
 
-    // async fn get_identifier_and_base_where_from_client(client: &'a BigqueryClient, pk_name: &str, table_name: &str) -> Result<(String, String), Box<dyn Error>>;
+    // async fn get_identifier_and_base_where_from_client(client: &'a BigqueryClient, pk_name: &str, table_name: &str) -> Result<(String, String), Error>;
 
     fn get_query_param<TField: BigDataValueType<TField>>(field_name: &str, field_value: &Option<TField>)
                                                          -> google_bigquery2::api::QueryParameter;
@@ -67,7 +91,7 @@ pub trait BigDataTableBaseConvenience<'a, TABLE, TPK>
     // fn create_from_table_row(client: &'a BigqueryClient,
     //                          row: &google_bigquery2::api::TableRow,
     //                          index_to_name_mapping: &HashMap<String, usize>)
-    //                          -> Result<Self, Box<dyn Error>>
+    //                          -> Result<Self, Error>
     //     where
     //         Self: Sized;
 }
@@ -113,16 +137,65 @@ impl<'a, TABLE, TPK> BigDataTableBaseConvenience<'a, TABLE, TPK> for TABLE
         values.join(", ")
     }
 
-    fn get_query_fields_update_str(&self) -> String {
-        let x = Self::get_query_fields();
+    fn get_update_fields(&self) -> Vec<String> {
         let pk_name = Self::get_pk_name();
-        let mut values = x.values()
-            .filter(|k| *k != &pk_name)
+        let mut fields: Vec<String> = match self.get_snapshot() {
+            Some(snapshot) => snapshot.get_changed(&self.get_all_query_parameters())
+                .iter()
+                .filter_map(|name| name.strip_prefix("__"))
+                .map(|name| name.to_string())
+                .collect(),
+            None => Self::get_query_fields().into_values().collect(),
+        };
+        fields.retain(|f| f != &pk_name);
+        fields.sort();
+        fields
+    }
+
+    fn get_query_fields_update_str(&self) -> String {
+        self.get_update_fields()
+            .iter()
             .map(|k| format!("{} = @__{}", k, k))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    fn get_update_query_parameters(&self) -> Vec<QueryParameter> {
+        let mut names = self.get_update_fields();
+        names.push(Self::get_pk_name());
+        let names: Vec<String> = names.iter().map(|name| format!("__{}", name)).collect();
+        self.get_all_query_parameters()
+            .into_iter()
+            .filter(|p| p.name.as_ref().is_some_and(|name| names.contains(name)))
+            .collect()
+    }
+
+    fn get_merge_query(table_identifier: &str, source: &str, update_fields: &[String], matched_condition: Option<&str>) -> String {
+        let pk_name = Self::get_pk_name();
+        let mut fields = Self::get_query_fields().into_values().collect::<Vec<String>>();
+        fields.sort();
+        let update = update_fields.iter()
+            .map(|f| format!("{} = S.{}", f, f))
+            .collect::<Vec<String>>();
+        let insert_values = fields.iter()
+            .map(|f| format!("S.{}", f))
+            .collect::<Vec<String>>();
+
+        let mut query = format!("merge into {} T using ({}) S on T.{} = S.{}", table_identifier, source, pk_name, pk_name);
+        if !update.is_empty() {
+            let condition = matched_condition.map(|c| format!(" and {}", c)).unwrap_or_default();
+            query = format!("{} when matched{} then update set {}", query, condition, update.join(", "));
+        }
+        format!("{} when not matched then insert ({}) values ({})", query, fields.join(", "), insert_values.join(", "))
+    }
+
+    fn get_merge_source_str(suffix: &str) -> String {
+        let mut values = Self::get_query_fields()
+            .into_values()
+            .map(|v| format!("@__{}{} as {}", v, suffix, v))
             .collect::<Vec<String>>();
         values.sort();
-        let update_str = values.join(", ");
-        update_str
+        format!("select {}", values.join(", "))
     }
 
     fn get_where_part(field_name: &str, is_comparing_to_null: bool) -> String {
@@ -133,17 +206,56 @@ impl<'a, TABLE, TPK> BigDataTableBaseConvenience<'a, TABLE, TPK> for TABLE
         }
     }
 
+    fn get_version_check(&self, table_alias: &str) -> Option<(String, QueryParameter)> {
+        let version_name = Self::get_version_name()?;
+        let parameter_name = format!("__{}", version_name);
+        let mut parameter = self.get_all_query_parameters()
+            .into_iter()
+            .find(|p| p.name.as_ref() == Some(&parameter_name))?;
+        parameter.name = Some(format!("{}_expected", parameter_name));
+        let column = match table_alias {
+            "" => version_name,
+            alias => format!("{}.{}", alias, version_name),
+        };
+        Some((format!("{} = @{}_expected", column, parameter_name), parameter))
+    }
+
+    async fn get_load_by_field_query<T: BigDataValueType<T> + Send>(client: &'a BigqueryClient,
+                                                                    field_name: &str,
+                                                                    field_value: Option<T>,
+                                                                    columns: Option<&[String]>,
+                                                                    max_amount: Option<usize>)
+                                                                    -> Result<(String, Vec<QueryParameter>), Error> {
+        let field_name = Self::get_field_name(field_name)?;
+        let where_clause = Self::get_where_part(&field_name, field_value.is_none());
+        let table_identifier = Self::get_identifier_from_client(client).await?;
+        let fields = match columns {
+            Some(columns) => columns.join(", "),
+            None => Self::get_query_fields_str(),
+        };
+        let mut query = format!("select {} from {} where {}", fields, table_identifier, where_clause);
+        if let Some(max_amount) = max_amount {
+            query = format!("{} limit {}", query, max_amount);
+        }
+
+        let mut params = vec![];
+        if field_value.is_some() {
+            params.push(Self::get_query_param(&field_name, &field_value));
+        }
+        Ok((query, params))
+    }
+
     //region run query
 
     async fn run_query(&self, req: QueryRequest, project_id: &str)
-                       -> Result<(Response<Body>, google_bigquery2::api::QueryResponse), Box<dyn Error>> {
+                       -> Result<google_bigquery2::api::QueryResponse, Error> {
         Self::run_query_on_client(self.get_client(), req, project_id).await
     }
 
     async fn run_query_on_client(client: &'a BigqueryClient,
                                  req: QueryRequest,
                                  project_id: &str)
-                                 -> Result<(Response<Body>, google_bigquery2::api::QueryResponse), Box<dyn Error>> {
+                                 -> Result<google_bigquery2::api::QueryResponse, Error> {
         #[cfg(debug_assertions="true")]
         {
             println!("Query: {}", &req.query.as_ref().unwrap());//There has to be a query, this would not make any sense otherwise
@@ -158,19 +270,12 @@ impl<'a, TABLE, TPK> BigDataTableBaseConvenience<'a, TABLE, TPK> for TABLE
             println!();
         }
 
-        let (res, query_res) = client.get_client().jobs().query(req, project_id)
-            .doit().await?;
-
-        if res.status() != 200 {
-            return Err(format!("Wrong status code returned! ({})", res.status()).into());
-        }
-
-        Ok((res, query_res))
+        client.run_query(req, project_id).await
     }
     //endregion run query
 
     async fn run_get_query(&self, query: &str, project_id: &str)
-                           -> Result<google_bigquery2::api::QueryResponse, Box<dyn Error>> {
+                           -> Result<google_bigquery2::api::QueryResponse, Error> {
         let parameters = vec![self.get_pk_param()];//default parameters (pk)
         self.run_get_query_with_params(query, parameters, project_id).await
     }
@@ -180,7 +285,7 @@ impl<'a, TABLE, TPK> BigDataTableBaseConvenience<'a, TABLE, TPK> for TABLE
                                        query: &str,
                                        parameters: Vec<google_bigquery2::api::QueryParameter>,
                                        project_id: &str)
-                                       -> Result<google_bigquery2::api::QueryResponse, Box<dyn Error>> {
+                                       -> Result<google_bigquery2::api::QueryResponse, Error> {
         let client = self.get_client();
         Self::run_get_query_with_params_on_client(client, query, parameters, project_id).await
     }
@@ -189,14 +294,14 @@ impl<'a, TABLE, TPK> BigDataTableBaseConvenience<'a, TABLE, TPK> for TABLE
                                                  query: &str,
                                                  parameters: Vec<google_bigquery2::api::QueryParameter>,
                                                  project_id: &str)
-                                                 -> Result<google_bigquery2::api::QueryResponse, Box<dyn Error>> {
+                                                 -> Result<google_bigquery2::api::QueryResponse, Error> {
         let req = google_bigquery2::api::QueryRequest {
             query: Some(query.to_string()),
             query_parameters: Some(parameters),
             use_legacy_sql: Some(false),
             ..Default::default()
         };
-        let (_, query_res) = Self::run_query_on_client(client, req, project_id).await?;
+        let query_res = Self::run_query_on_client(client, req, project_id).await?;
         // let (res, query_res) = client.get_client().jobs().query(req, project_id)
         //     .doit().await?;
         //
@@ -206,16 +311,16 @@ impl<'a, TABLE, TPK> BigDataTableBaseConvenience<'a, TABLE, TPK> for TABLE
         Ok(query_res)
     }
     // async fn get_identifier_and_base_where(&self)
-    //                                        -> Result<(String, String), Box<dyn Error>> {
+    //                                        -> Result<(String, String), Error> {
     //     let pk_name = Self::get_pk_name();
     //     let table_name = Self::get_table_name();
     //     Ok(Self::get_identifier_and_base_where_from_client(&self.get_client(), &pk_name, &table_name).await?)
     // }
-    async fn get_identifier(&self) -> Result<String, Box<dyn Error>> {
+    async fn get_identifier(&self) -> Result<String, Error> {
         let client = self.get_client();
         Self::get_identifier_from_client(&client).await
     }
-    async fn get_identifier_from_client(client: &'a BigqueryClient) -> Result<String, Box<dyn Error>> {
+    async fn get_identifier_from_client(client: &'a BigqueryClient) -> Result<String, Error> {
         let dataset_id = client.get_dataset_id();
         let table_name = Self::get_table_name();
         let table_identifier = format!("{}.{}", dataset_id, table_name);
@@ -229,24 +334,7 @@ impl<'a, TABLE, TPK> BigDataTableBaseConvenience<'a, TABLE, TPK> for TABLE
 
     fn get_query_param<TField: BigDataValueType<TField>>(field_name: &str, field_value: &Option<TField>) -> google_bigquery2::api::QueryParameter
     {
-        let type_to_string: String = TField::to_bigquery_type();
-        let value: Option<google_bigquery2::api::QueryParameterValue> = Some(google_bigquery2::api::QueryParameterValue {
-            value: match field_value {
-                Some(value) => Some(value.to_bigquery_param_value()),//TODO: maybe add a way to use array types
-                None => None,
-            },
-            ..Default::default()
-        });
-
-        google_bigquery2::api::QueryParameter {
-            name: Some(format!("__{}", field_name.clone())),
-            parameter_type: Some(google_bigquery2::api::QueryParameterType {
-                type_: Some(type_to_string),
-                ..Default::default()
-            }),
-            parameter_value: value,
-            ..Default::default()
-        }
+        crate::utils::get_query_param(&format!("__{}", field_name), field_value)
     }
     fn parse_value_to_parameter<TValue>(value: &TValue) -> String
         where TValue: std::fmt::Display + BigDataValueType<TValue>
@@ -258,7 +346,7 @@ impl<'a, TABLE, TPK> BigDataTableBaseConvenience<'a, TABLE, TPK> for TABLE
     // fn create_from_table_row(client: &'a BigqueryClient,
     //                          row: &google_bigquery2::api::TableRow,
     //                          index_to_name_mapping: &HashMap<String, usize>)
-    //                          -> Result<Self, Box<dyn Error>>
+    //                          -> Result<Self, Error>
     //     where
     //         Self: Sized
     // {
@@ -0,0 +1,116 @@
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+
+/// A field of `TABLE` whose values have the type `T` (the inner type for `Option` fields).
+///
+/// The `BigDataTable` derive generates one for every field, e.g. `Infos::COLUMNS.info1`,
+/// so filters can refer to fields without strings: a wrong name or a value of the wrong type
+/// does not compile.
+pub struct Column<TABLE, T> {
+    name: &'static str,
+    table: PhantomData<fn() -> (TABLE, T)>,
+}
+
+impl<TABLE, T> Column<TABLE, T> {
+    /// The column of the field with the name `name` (the name of the struct field, not the one in the database).
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            table: PhantomData,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl<TABLE, T> Clone for Column<TABLE, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<TABLE, T> Copy for Column<TABLE, T> {}
+
+impl<TABLE, T> Debug for Column<TABLE, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Column").field(&self.name).finish()
+    }
+}
+
+/// A field of `TABLE`: a [`Column`] or the name of the field.
+pub trait TableField<TABLE> {
+    fn field_name(&self) -> &str;
+}
+
+/// A field of `TABLE` with values of type `T`: a [`Column`], or an [`UntypedField`] that can have values of any type.
+///
+/// ```
+/// # use google_bigquery::{BigDataTableDerive, BigqueryClient, Filter, HasBigQueryClientDerive, UntypedField};
+/// # use google_bigquery::{BigDataTableBase, BigDataTableBaseConvenience, BigDataTableHasPk, HasBigQueryClient};
+/// # use google_bigquery::utils::ConvertValueToBigqueryParamValue;
+/// # #[derive(Default, HasBigQueryClientDerive, BigDataTableDerive)]
+/// # struct Infos<'a> {
+/// #     #[primary_key]
+/// #     #[required]
+/// #     id: String,
+/// #     #[client]
+/// #     client: Option<&'a BigqueryClient>,
+/// #     info1: Option<String>,
+/// # }
+/// let filter: Filter<Infos, String> = Filter::all()
+///     .eq(Infos::COLUMNS.info1, "a".to_string())
+///     .eq(UntypedField::new("info1"), 42i64);
+/// ```
+///
+/// Plain names of fields can not be compared with values:
+/// ```compile_fail
+/// # use google_bigquery::{BigDataTableDerive, BigqueryClient, Filter, HasBigQueryClientDerive, UntypedField};
+/// # use google_bigquery::{BigDataTableBase, BigDataTableBaseConvenience, BigDataTableHasPk, HasBigQueryClient};
+/// # use google_bigquery::utils::ConvertValueToBigqueryParamValue;
+/// # #[derive(Default, HasBigQueryClientDerive, BigDataTableDerive)]
+/// # struct Infos<'a> {
+/// #     #[primary_key]
+/// #     #[required]
+/// #     id: String,
+/// #     #[client]
+/// #     client: Option<&'a BigqueryClient>,
+/// #     info1: Option<String>,
+/// # }
+/// let filter: Filter<Infos, String> = Filter::all().eq("info1", 42i64);
+/// ```
+pub trait TypedTableField<TABLE, T>: TableField<TABLE> {}
+
+/// The name of a field (the one of the struct field) whose values are not checked against its type
+/// at compile time, e.g. for fields only known at runtime. Prefer the [`Column`]s of the table.
+#[derive(Debug, Clone, Copy)]
+pub struct UntypedField<'f>(&'f str);
+
+impl<'f> UntypedField<'f> {
+    pub fn new(name: &'f str) -> Self {
+        Self(name)
+    }
+}
+
+impl<TABLE> TableField<TABLE> for &str {
+    fn field_name(&self) -> &str {
+        self
+    }
+}
+
+impl<TABLE> TableField<TABLE> for UntypedField<'_> {
+    fn field_name(&self) -> &str {
+        self.0
+    }
+}
+
+impl<TABLE, T> TypedTableField<TABLE, T> for UntypedField<'_> {}
+
+impl<TABLE, T> TableField<TABLE> for Column<TABLE, T> {
+    fn field_name(&self) -> &str {
+        self.name
+    }
+}
+
+impl<TABLE, T> TypedTableField<TABLE, T> for Column<TABLE, T> {}
@@ -1,20 +1,29 @@
 use std::collections::HashMap;
-use std::error::Error;
 use std::fmt::{Debug, Display};
 use std::str::FromStr;
 
 use async_trait::async_trait;
-use google_bigquery2::api::{QueryParameter, TableSchema};
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use google_bigquery2::api::{QueryParameter, QueryRequest, TableSchema};
 
 pub use big_data_table_base::BigDataTableBase;
 pub use big_data_table_base::BigDataTableHasPk;
+pub use big_data_table_base::BigDataTableHasSnapshot;
+pub use big_data_table_base::RestoreVersion;
 pub use big_data_table_base_convenience::BigDataTableBaseConvenience;
+pub use column::{Column, TableField, TypedTableField, UntypedField};
+pub use query_builder::{Filter, Order, QueryBuilder};
+pub use snapshot::Snapshot;
 
-use crate::client::{BigqueryClient, HasBigQueryClient};
-use crate::utils::BigDataValueType;
+use crate::client::{BigqueryClient, HasBigQueryClient, SaveMode};
+use crate::error::Error;
+use crate::utils::{get_table_row_value, BigDataValueType};
 
 mod big_data_table_base_convenience;
 mod big_data_table_base;
+mod column;
+mod query_builder;
+mod snapshot;
 
 // pub trait BigDataTable<'a, TABLE, TPK: BigDataValueType<TPK> + FromStr + Debug>: HasBigQueryClient<'a> + BigDataTableBaseConvenience<'a, TABLE, TPK> + BigDataTableBase<'a, TABLE, TPK> {
 #[async_trait]
@@ -29,21 +38,160 @@ pub trait BigDataTable<'a, TABLE, TPK>
     async fn create_and_load_from_pk(
         client: &'a BigqueryClient,
         pk: TPK,
-    ) -> Result<Self, Box<dyn Error>>
+    ) -> Result<Self, Error>
         where
             Self: Sized,
             TPK: 'async_trait;
-    async fn load_from_pk(client: &'a BigqueryClient, pk: TPK) -> Result<Option<Self>, Box<dyn Error>> where Self: Sized,
+    async fn load_from_pk(client: &'a BigqueryClient, pk: TPK) -> Result<Option<Self>, Error> where Self: Sized,
                                                                                                              TPK: 'async_trait;
-    async fn save_to_bigquery(&self) -> Result<(), Box<dyn Error>>;
-    async fn load_from_bigquery(&mut self) -> Result<(), Box<dyn Error>>;
+    /// Inserts the row, or updates it if it exists already.
+    ///
+    /// If the struct has a `#[version]` field, it is incremented and an update only succeeds if the row
+    /// still has the previous version, otherwise the save fails with [`Error::VersionConflict`]
+    /// (and the field keeps its previous version).
+    async fn save_to_bigquery(&mut self) -> Result<(), Error>;
+    /// Upserts all rows with `MERGE` statements on the primary key, as few as the query limits allow,
+    /// and returns the number of affected rows.
+    ///
+    /// Each statement is atomic, but the rows are split into several statements if there are too many.
+    /// A primary key must not appear more than once in `rows`.
+    /// Not supported for structs with a `#[version]` field and for rows that were loaded with only some of their fields
+    /// (see [`load_by_field_with_columns`](Self::load_by_field_with_columns)), save them with [`save_to_bigquery`](Self::save_to_bigquery).
+    async fn save_many_to_bigquery(client: &'a BigqueryClient, rows: &[TABLE]) -> Result<u64, Error>
+        where TABLE: 'async_trait;
+    /// Inserts the row without checking whether it exists first and returns the number of inserted rows.
+    /// Fails with [`Error::DuplicatePrimaryKey`] if a row with the primary key exists already.
+    async fn insert_to_bigquery(&mut self) -> Result<u64, Error>;
+    /// Updates the row without checking whether it exists first and returns the number of updated rows.
+    /// Fails with [`Error::NotFound`] if there is no row with the primary key,
+    /// and with [`Error::VersionConflict`] if it does not have the version of the `#[version]` field anymore.
+    ///
+    /// If no field changed since the row was loaded or saved (see [`Snapshot`]), nothing is written
+    /// and `0` is returned, but the row is still checked to exist with that version.
+    async fn update_in_bigquery(&mut self) -> Result<u64, Error>;
+    async fn load_from_bigquery(&mut self) -> Result<(), Error>;
     async fn load_by_field<T: BigDataValueType<T> + Send>(client: &'a BigqueryClient, field_name: &str, field_value: Option<T>, max_amount: usize)
-                                                          -> Result<Vec<TABLE>, Box<dyn Error>>
+                                                          -> Result<Vec<TABLE>, Error>
+        where TABLE: 'async_trait;
+    /// All rows where the field has one of the `field_values`, in no particular order,
+    /// loaded with a single `IN UNNEST(@..)` query with an array parameter.
+    async fn load_by_field_in<T: BigDataValueType<T> + Send>(client: &'a BigqueryClient, field_name: &str, field_values: Vec<T>)
+                                                             -> Result<Vec<TABLE>, Error>
+        where TABLE: 'async_trait;
+    /// The rows with the primary keys in no particular order, loaded with a single query
+    /// instead of one [`load_from_pk`](Self::load_from_pk) per key. Keys without a row are left out.
+    async fn load_many_from_pk(client: &'a BigqueryClient, pks: Vec<TPK>) -> Result<Vec<TABLE>, Error>
+        where TABLE: 'async_trait,
+              TPK: 'async_trait;
+    /// Like [`load_by_field`](Self::load_by_field) with a [`Column`] instead of the name of the field.
+    async fn load_by_column<T: BigDataValueType<T> + Send>(client: &'a BigqueryClient, column: Column<TABLE, T>, field_value: Option<T>, max_amount: usize)
+                                                           -> Result<Vec<TABLE>, Error>
+        where TABLE: 'async_trait;
+
+    /// Like [`load_by_field`](Self::load_by_field), but only loads the `columns` (names of fields),
+    /// the primary key and the `#[version]` field. The other fields keep their default values.
+    ///
+    /// The loaded columns are recorded in the `#[snapshot]` field (see [`get_loaded_fields`](BigDataTableBase::get_loaded_fields)),
+    /// so saving such a row only writes the fields that were changed. Only available for structs with such
+    /// a field (see [`BigDataTableHasSnapshot`]).
+    async fn load_by_field_with_columns<T: BigDataValueType<T> + Send>(client: &'a BigqueryClient,
+                                                                       field_name: &str,
+                                                                       field_value: Option<T>,
+                                                                       columns: &[&str],
+                                                                       max_amount: usize)
+                                                                       -> Result<Vec<TABLE>, Error>
+        where TABLE: 'async_trait + BigDataTableHasSnapshot;
+
+    /// Deletes the row with the primary key of `self` and returns the number of deleted rows.
+    async fn delete_from_bigquery(&self) -> Result<u64, Error>;
+    /// Deletes all rows where the field has the value (or is NULL) and returns the number of deleted rows.
+    async fn delete_by_field<T: BigDataValueType<T> + Send>(client: &'a BigqueryClient, field_name: &str, field_value: Option<T>)
+                                                            -> Result<u64, Error>
+        where TABLE: 'async_trait;
+    /// Deletes all rows that match `condition` (the part after `where`) and returns the number of deleted rows.
+    async fn delete_by_custom_query(client: &'a BigqueryClient, condition: &str, parameters: Vec<QueryParameter>)
+                                    -> Result<u64, Error>
         where TABLE: 'async_trait;
 
+    /// Loads the first `max_amount` rows of the query, only reading as many pages of the result as that takes.
     async fn load_by_custom_query(client: &'a BigqueryClient, query: &str, parameters: Vec<QueryParameter>, max_amount: usize)
-                                  -> Result<Vec<TABLE>, Box<dyn Error>>
+                                  -> Result<Vec<TABLE>, Error>
+        where TABLE: 'async_trait;
+
+    /// Like [`load_by_field`](Self::load_by_field), but rows that can not be decoded are skipped
+    /// and their errors are returned next to the other rows instead of failing the whole load.
+    async fn load_by_field_lenient<T: BigDataValueType<T> + Send>(client: &'a BigqueryClient, field_name: &str, field_value: Option<T>, max_amount: usize)
+                                                                  -> Result<LenientLoad<TABLE>, Error>
+        where TABLE: 'async_trait;
+
+    /// Like [`load_by_custom_query`](Self::load_by_custom_query) for a query that only selects the `columns`
+    /// (names of fields) and the primary key, see [`load_by_field_with_columns`](Self::load_by_field_with_columns).
+    async fn load_by_custom_query_with_columns(client: &'a BigqueryClient,
+                                               query: &str,
+                                               parameters: Vec<QueryParameter>,
+                                               columns: &[&str],
+                                               max_amount: usize)
+                                               -> Result<Vec<TABLE>, Error>
+        where TABLE: 'async_trait + BigDataTableHasSnapshot;
+
+    /// Like [`load_by_custom_query`](Self::load_by_custom_query), but rows that can not be decoded are skipped
+    /// and their errors are returned next to the other rows instead of failing the whole load.
+    async fn load_by_custom_query_lenient(client: &'a BigqueryClient, query: &str, parameters: Vec<QueryParameter>, max_amount: usize)
+                                          -> Result<LenientLoad<TABLE>, Error>
         where TABLE: 'async_trait;
+
+    /// Like [`load_by_field`](Self::load_by_field) without a limit, but returns the rows as a stream
+    /// (see [`stream_by_custom_query`](Self::stream_by_custom_query)).
+    fn stream_by_field<T: BigDataValueType<T> + Send + 'a>(client: &'a BigqueryClient, field_name: &str, field_value: Option<T>)
+                                                           -> BoxStream<'a, Result<TABLE, Error>>
+        where TABLE: 'a,
+              TPK: 'a;
+
+    /// Like [`load_by_custom_query`](Self::load_by_custom_query), but returns the rows as a stream.
+    ///
+    /// The pages of the result are only fetched while the stream is polled, so only one page has to be
+    /// in memory at a time. Rows that can not be decoded are returned as errors without ending the stream.
+    fn stream_by_custom_query(client: &'a BigqueryClient, query: &str, parameters: Vec<QueryParameter>)
+                              -> BoxStream<'a, Result<TABLE, Error>>
+        where TABLE: 'a,
+              TPK: 'a;
+
+    /// The number of rows of the table.
+    async fn count(client: &'a BigqueryClient) -> Result<u64, Error>
+        where TABLE: 'async_trait;
+    /// The number of rows where the field has the value (or is NULL).
+    async fn count_by_field<T: BigDataValueType<T> + Send>(client: &'a BigqueryClient, field_name: &str, field_value: Option<T>)
+                                                           -> Result<u64, Error>
+        where TABLE: 'async_trait;
+    /// Whether a row with the primary key exists, without loading it.
+    async fn exists_by_pk(client: &'a BigqueryClient, pk: TPK) -> Result<bool, Error>
+        where TABLE: 'async_trait,
+              TPK: 'async_trait;
+    /// The distinct values of the field in no particular order, `None` for NULL.
+    async fn distinct_values<T: BigDataValueType<T> + Send>(client: &'a BigqueryClient, field_name: &str)
+                                                            -> Result<Vec<Option<T>>, Error>
+        where TABLE: 'async_trait;
+
+    /// Starts a query for the rows that match a [`Filter`], with ordering and paging (see [`QueryBuilder`]).
+    fn query(client: &'a BigqueryClient) -> QueryBuilder<'a, TABLE, TPK>
+        where TABLE: 'a,
+              TPK: 'a;
+}
+
+/// The most query parameters BigQuery accepts in one query.
+const MAX_QUERY_PARAMETERS: usize = 10_000;
+/// The longest query (in characters) BigQuery accepts.
+const MAX_QUERY_LENGTH: usize = 1024 * 1024;
+/// The largest query and parameters (in bytes of JSON) sent in one request. BigQuery accepts requests of
+/// up to 10 MB, the rest is left for the other fields of the request.
+const MAX_REQUEST_SIZE: usize = 10_000_000 - 64 * 1024;
+const UNION_ALL: &str = " union all ";
+
+/// The result of a lenient load: all rows that could be decoded and the errors of the ones that could not.
+#[derive(Debug)]
+pub struct LenientLoad<TABLE> {
+    pub rows: Vec<TABLE>,
+    pub errors: Vec<Error>,
 }
 
 #[async_trait]
@@ -53,14 +201,14 @@ impl<'a, TABLE, TPK> BigDataTable<'a, TABLE, TPK> for TABLE
         TPK: BigDataValueType<TPK> + FromStr + Debug + Clone + Send,
         <TPK as FromStr>::Err: Debug
 {
-    async fn create_and_load_from_pk(client: &'a BigqueryClient, pk: TPK) -> Result<Self, Box<dyn Error>> where Self: Sized,
+    async fn create_and_load_from_pk(client: &'a BigqueryClient, pk: TPK) -> Result<Self, Error> where Self: Sized,
                                                                                                                 TPK: 'async_trait {
         let mut res = Self::create_with_pk(client, pk);
         res.load_from_bigquery().await?;
         Ok(res)
     }
 
-    async fn load_from_pk(client: &'a BigqueryClient, pk: TPK) -> Result<Option<Self>, Box<dyn Error>>
+    async fn load_from_pk(client: &'a BigqueryClient, pk: TPK) -> Result<Option<Self>, Error>
         where Self: Sized,
               TPK: 'async_trait {
         let x = Self::load_by_field(client, &Self::get_pk_name(), Some(pk), 1).await
@@ -68,92 +216,234 @@ impl<'a, TABLE, TPK> BigDataTable<'a, TABLE, TPK> for TABLE
         Ok(x)
     }
 
-    async fn save_to_bigquery(&self) -> Result<(), Box<dyn Error>> {
-        let project_id = self.get_client().get_project_id();
-
-        let table_identifier = self.get_identifier().await?;
-        let where_clause = Self::get_base_where();
-        // region check for existing data
-        let exists_row: bool;
-        let existing_count = format!("select count(*) from {} where {} limit 1", table_identifier, where_clause);
-
-        let req = google_bigquery2::api::QueryRequest {
-            query: Some(existing_count),
-            query_parameters: Some(vec![self.get_pk_param()]),
-            use_legacy_sql: Some(false),
-            ..Default::default()
-        };
-
-        let (_, query_res) = self.run_query(req, project_id).await?;
-        // let (res, query_res) = self.get_client().get_client().jobs().query(req, project_id)
-        //     .doit().await?;
-        //
-        // if res.status() != 200 {
-        //     return Err(format!("Wrong status code returned! ({})", res.status()).into());
-        // }
-
-        if let None = &query_res.rows {
-            return Err("No rows returned!".into());
-        }
-
-        let rows = query_res.rows.unwrap();
-
-        if rows.len() != 1 {
-            return Err(format!("Wrong amount of data returned! ({})", rows.len()).into());
-        }
-
-        let row = &rows[0];
-        let amount: i32 = row.f.as_ref().unwrap()[0].clone().v.unwrap().parse().unwrap();
-
-        if amount == 0 {
-            exists_row = false;
-        } else if amount == 1 {
-            exists_row = true;
-        } else {
-            panic!("Too many rows with key!");
+    async fn save_to_bigquery(&mut self) -> Result<(), Error> {
+        if self.get_update_fields().is_empty() && self.get_snapshot().is_some_and(|snapshot| snapshot.is_set()) {
+            // nothing changed since the row was loaded or saved
+            return Ok(());
         }
+        let version_check = self.get_version_check("");
+        let matched_condition = self.get_version_check("T");
+        let restore_version = self.increment_version();
+        let row = &*self;
+        let result = async {
+            let project_id = row.get_client().get_project_id();
+            let table_identifier = row.get_identifier().await?;
+            let update_fields = row.get_update_fields();
+            if row.get_client().get_save_mode() == SaveMode::Merge {
+                let mut query_parameters = row.get_all_query_parameters();
+                query_parameters.extend(matched_condition.as_ref().map(|(_, parameter)| parameter.clone()));
+                let req = QueryRequest {
+                    query: Some(Self::get_merge_query(&table_identifier, &Self::get_merge_source_str(""), &update_fields,
+                                                      matched_condition.as_ref().map(|(condition, _)| condition.as_str()))),
+                    query_parameters: Some(query_parameters),
+                    use_legacy_sql: Some(false),
+                    ..Default::default()
+                };
+                let query_res = row.run_query(req, project_id).await?;
+                if matched_condition.is_some() && get_affected_rows(&query_res)? == 0 {
+                    return Err(version_conflict(row, table_identifier));
+                }
+                return Ok(());
+            }
 
-        // endregion
+            let mut where_clause = Self::get_base_where();
+            // region check for existing data
+            let exists_row: bool;
+            let existing_count = format!("select count(*) from {} where {} limit 1", table_identifier, where_clause);
+
+            let req = google_bigquery2::api::QueryRequest {
+                query: Some(existing_count),
+                query_parameters: Some(vec![row.get_pk_param()]),
+                use_legacy_sql: Some(false),
+                ..Default::default()
+            };
+
+            let query_res = row.run_query(req, project_id).await?;
+            // let (res, query_res) = self.get_client().get_client().jobs().query(req, project_id)
+            //     .doit().await?;
+            //
+            // if res.status() != 200 {
+            //     return Err(format!("Wrong status code returned! ({})", res.status()).into());
+            // }
+
+            let amount = get_count(query_res)?;
+
+            if amount == 0 {
+                exists_row = false;
+            } else if amount == 1 {
+                exists_row = true;
+            } else {
+                return Err(Error::DuplicatePrimaryKey {
+                    table: table_identifier,
+                    primary_key: format!("{:?}", row.get_pk_value()),
+                });
+            }
 
+            // endregion
 
-        // region update or insert
 
-        let query = match exists_row {
-            true => format!("update {} set {} where {}", table_identifier, self.get_query_fields_update_str(), where_clause),
-            // false => format!("insert into {} ({}, {}) values(@__{}, {})", table_identifier,
-            //                  Self::get_pk_name(),
-            //                  Self::get_query_fields_str(),
-            //                  Self::get_pk_name(),
-            //                  Self::get_query_fields_insert_str()),
-            false => format!("insert into {} ({}) values({})", table_identifier,
-                             Self::get_query_fields_str(),
-                             Self::get_query_fields_insert_str()),
-        };
+            // region update or insert
 
-        let mut query_parameters = self.get_all_query_parameters();
-        // query_parameters.push(self.get_pk_param()); // todo: check if this is needed
-        let req = google_bigquery2::api::QueryRequest {
-            query: Some(query),
-            query_parameters: Some(query_parameters),
-            use_legacy_sql: Some(false),
-            ..Default::default()
-        };
+            if exists_row && update_fields.is_empty() {
+                return Ok(());
+            }
+            let mut query_parameters = match exists_row {
+                true => row.get_update_query_parameters(),
+                false => row.get_all_query_parameters(),
+            };
+            if let (true, Some((condition, parameter))) = (exists_row, &version_check) {
+                where_clause = format!("{} and {}", where_clause, condition);
+                query_parameters.push(parameter.clone());
+            }
+            let query = match exists_row {
+                true => format!("update {} set {} where {}", table_identifier, row.get_query_fields_update_str(), where_clause),
+                // false => format!("insert into {} ({}, {}) values(@__{}, {})", table_identifier,
+                //                  Self::get_pk_name(),
+                //                  Self::get_query_fields_str(),
+                //                  Self::get_pk_name(),
+                //                  Self::get_query_fields_insert_str()),
+                false => format!("insert into {} ({}) values({})", table_identifier,
+                                 Self::get_query_fields_str(),
+                                 Self::get_query_fields_insert_str()),
+            };
+
+            // query_parameters.push(self.get_pk_param()); // todo: check if this is needed
+            let req = google_bigquery2::api::QueryRequest {
+                query: Some(query),
+                query_parameters: Some(query_parameters),
+                use_legacy_sql: Some(false),
+                ..Default::default()
+            };
+
+
+            let query_res = row.run_query(req, project_id).await?;
+            // let (res, _) = self.get_client().get_client().jobs().query(req, project_id)
+            //     .doit().await?;
+            //
+            // if res.status() != 200 {
+            //     return Err(format!("Wrong status code returned! ({})", res.status()).into());
+            // }
+            if exists_row && version_check.is_some() && get_affected_rows(&query_res)? == 0 {
+                return Err(version_conflict(row, table_identifier));
+            }
 
+            //endregion
+            Ok(())
+        }.await;
+        finish_write(self, restore_version, result)
+    }
 
-        let (_, _) = self.run_query(req, project_id).await?;
-        // let (res, _) = self.get_client().get_client().jobs().query(req, project_id)
-        //     .doit().await?;
-        //
-        // if res.status() != 200 {
-        //     return Err(format!("Wrong status code returned! ({})", res.status()).into());
-        // }
+    async fn save_many_to_bigquery(client: &'a BigqueryClient, rows: &[TABLE]) -> Result<u64, Error> {
+        if let Some(version_name) = Self::get_version_name() {
+            return Err(Error::Other(format!("Can not save many rows of {} at once, since it has the version field {}",
+                                            Self::get_table_name(), version_name)));
+        }
+        if let Some(row) = rows.iter().find(|row| row.get_loaded_fields().is_some()) {
+            return Err(Error::Other(format!("Can not save many rows of {} at once, since the row with primary key {:?} was loaded with only some of its fields",
+                                            Self::get_table_name(), row.get_pk_value())));
+        }
+        let table_identifier = Self::get_identifier_from_client(client).await?;
+        let pk_name = Self::get_pk_name();
+        let mut update_fields: Vec<String> = Self::get_query_fields().into_values()
+            .filter(|field| field != &pk_name)
+            .collect();
+        update_fields.sort();
+        let merge_length = Self::get_merge_query(&table_identifier, "", &update_fields, None).len();
+
+        let mut affected_rows = 0;
+        let mut sources: Vec<String> = vec![];
+        let mut query_parameters = vec![];
+        let mut parameters_size = 0;
+        let mut chunk_start = 0;
+        for (i, row) in rows.iter().enumerate() {
+            let suffix = format!("_{}", i);
+            let source = Self::get_merge_source_str(&suffix);
+            let mut row_parameters = row.get_all_query_parameters();
+            for parameter in row_parameters.iter_mut() {
+                parameter.name = parameter.name.take().map(|name| format!("{}{}", name, suffix));
+            }
+            let row_parameters_size = get_parameters_size(&row_parameters);
+            let length = merge_length + sources.iter().map(|s| s.len() + UNION_ALL.len()).sum::<usize>() + source.len();
+            if !sources.is_empty()
+                && (query_parameters.len() + row_parameters.len() > MAX_QUERY_PARAMETERS
+                || length > MAX_QUERY_LENGTH
+                || length + parameters_size + row_parameters_size > MAX_REQUEST_SIZE) {
+                affected_rows += run_merge(client, &table_identifier, &sources, &update_fields, query_parameters, &rows[chunk_start..i]).await?;
+                sources = vec![];
+                query_parameters = vec![];
+                parameters_size = 0;
+                chunk_start = i;
+            }
+            sources.push(source);
+            query_parameters.extend(row_parameters);
+            parameters_size += row_parameters_size;
+        }
+        if !sources.is_empty() {
+            affected_rows += run_merge(client, &table_identifier, &sources, &update_fields, query_parameters, &rows[chunk_start..]).await?;
+        }
+        Ok(affected_rows)
+    }
 
-        //endregion
+    async fn insert_to_bigquery(&mut self) -> Result<u64, Error> {
+        let restore_version = self.increment_version();
+        let row = &*self;
+        let result = async {
+            let project_id = row.get_client().get_project_id();
+            let table_identifier = row.get_identifier().await?;
+            // a merge that only inserts, so an existing row is reported instead of duplicated
+            let query = Self::get_merge_query(&table_identifier, &Self::get_merge_source_str(""), &[], None);
+            let query_res = row.run_get_query_with_params(&query, row.get_all_query_parameters(), project_id).await?;
+            match get_affected_rows(&query_res)? {
+                0 => Err(Error::DuplicatePrimaryKey {
+                    table: table_identifier,
+                    primary_key: format!("{:?}", row.get_pk_value()),
+                }),
+                affected_rows => Ok(affected_rows),
+            }
+        }.await;
+        finish_write(self, restore_version, result)
+    }
 
-        Ok(())
+    async fn update_in_bigquery(&mut self) -> Result<u64, Error> {
+        if self.get_update_fields().is_empty() {
+            let table_identifier = self.get_identifier().await?;
+            let mut where_clause = Self::get_base_where();
+            let mut query_parameters = vec![self.get_pk_param()];
+            if let Some((condition, parameter)) = self.get_version_check("") {
+                where_clause = format!("{} and {}", where_clause, condition);
+                query_parameters.push(parameter);
+            }
+            let query = format!("select count(*) from {} where {}", table_identifier, where_clause);
+            let query_res = self.run_get_query_with_params(&query, query_parameters, self.get_client().get_project_id()).await?;
+            return match get_count(query_res)? {
+                0 => Err(get_missing_row_error(self, table_identifier).await),
+                _ => Ok(0),
+            };
+        }
+        let version_check = self.get_version_check("");
+        let restore_version = self.increment_version();
+        let row = &*self;
+        let result = async {
+            let project_id = row.get_client().get_project_id();
+            let table_identifier = row.get_identifier().await?;
+            let mut where_clause = Self::get_base_where();
+            let mut query_parameters = row.get_update_query_parameters();
+            if let Some((condition, parameter)) = &version_check {
+                where_clause = format!("{} and {}", where_clause, condition);
+                query_parameters.push(parameter.clone());
+            }
+            let query = format!("update {} set {} where {}", table_identifier, row.get_query_fields_update_str(), where_clause);
+            let query_res = row.run_get_query_with_params(&query, query_parameters, project_id).await?;
+            match get_affected_rows(&query_res)? {
+                0 if version_check.is_some() => Err(get_missing_row_error(row, table_identifier).await),
+                0 => Err(Error::NotFound(format!("No row with {} = {:?} in {}", Self::get_pk_name(), row.get_pk_value(), table_identifier))),
+                affected_rows => Ok(affected_rows),
+            }
+        }.await;
+        finish_write(self, restore_version, result)
     }
 
-    async fn load_from_bigquery(&mut self) -> Result<(), Box<dyn Error>> {
+    async fn load_from_bigquery(&mut self) -> Result<(), Error> {
         let project_id = self.get_client().get_project_id();
         let table_identifier = self.get_identifier().await?;
         let where_clause = Self::get_base_where();
@@ -161,16 +451,16 @@ impl<'a, TABLE, TPK> BigDataTable<'a, TABLE, TPK> for TABLE
         let query = format!("select {} from {} where {} limit 1", Self::get_query_fields_str(), table_identifier, where_clause);
         let query_res = self.run_get_query(&query, project_id).await?;
 
-        if let None = &query_res.rows {
-            return Err("No rows returned!".into());
+        if query_res.rows.as_ref().filter(|rows| !rows.is_empty()).is_none() {
+            return Err(Error::NotFound(format!("No row with {} = {:?} in {}", Self::get_pk_name(), self.get_pk_value(), table_identifier)));
         }
 
         let rows = query_res.rows.unwrap();
 
         if rows.len() != 1 {
-            return Err(format!("Wrong amount of data returned! ({})", rows.len()).into());
+            return Err(Error::InvalidResponse(format!("Wrong amount of data returned! ({})", rows.len())));
         }
-        let mut index_to_name_mapping: HashMap<String, usize> = get_name_index_mapping(query_res.schema);
+        let mut index_to_name_mapping: HashMap<String, usize> = get_name_index_mapping(query_res.schema)?;
         // println!("index_to_name_mapping: {:?}", index_to_name_mapping);
 
         let row = &rows[0];
@@ -178,52 +468,432 @@ impl<'a, TABLE, TPK> BigDataTable<'a, TABLE, TPK> for TABLE
     }
 
     async fn load_by_field<T: BigDataValueType<T> + Send>(client: &'a BigqueryClient, field_name: &str, field_value: Option<T>, max_amount: usize)
-                                                          -> Result<Vec<TABLE>, Box<dyn Error>>
+                                                          -> Result<Vec<TABLE>, Error>
     {
-        let field_name: String = field_name.into();
-        let field_name = Self::get_field_name(&field_name).expect(format!("Field '{}' not found!", field_name).as_str());
-        let where_clause = Self::get_where_part(&field_name, field_value.is_none());
-        // let where_clause = format!(" {} = @__{}", field_name, field_name);
-        let table_identifier = Self::get_identifier_from_client(client).await?;
-        let query = format!("select {} from {} where {} limit {}", Self::get_query_fields_str(), table_identifier, where_clause, max_amount);
+        let (query, params) = Self::get_load_by_field_query(client, field_name, field_value, None, Some(max_amount)).await?;
+        Self::load_by_custom_query(client, &query, params, max_amount).await
+    }
+
+    async fn load_by_field_in<T: BigDataValueType<T> + Send>(client: &'a BigqueryClient, field_name: &str, field_values: Vec<T>)
+                                                             -> Result<Vec<TABLE>, Error> {
+        let field_name = Self::get_field_name(field_name)?;
+        load_in(client, &field_name, &field_name, field_values).await
+    }
+
+    async fn load_many_from_pk(client: &'a BigqueryClient, pks: Vec<TPK>) -> Result<Vec<TABLE>, Error>
+        where TPK: 'async_trait {
+        load_in(client, &Self::get_pk_name(), "ids", pks).await
+    }
+
+    async fn load_by_column<T: BigDataValueType<T> + Send>(client: &'a BigqueryClient, column: Column<TABLE, T>, field_value: Option<T>, max_amount: usize)
+                                                           -> Result<Vec<TABLE>, Error>
+        where TABLE: 'async_trait {
+        Self::load_by_field(client, column.name(), field_value, max_amount).await
+    }
+
+    async fn load_by_field_with_columns<T: BigDataValueType<T> + Send>(client: &'a BigqueryClient,
+                                                                       field_name: &str,
+                                                                       field_value: Option<T>,
+                                                                       columns: &[&str],
+                                                                       max_amount: usize)
+                                                                       -> Result<Vec<TABLE>, Error>
+        where TABLE: BigDataTableHasSnapshot {
+        let columns = get_projection::<TABLE, TPK>(columns)?;
+        let (query, params) = Self::get_load_by_field_query(client, field_name, field_value, Some(&columns), Some(max_amount)).await?;
+        let query_res = Self::run_get_query_with_params_on_client(client, &query, params, client.get_project_id()).await?;
+        decode_rows(client, query_res, Some(&columns), false).map(|loaded| loaded.rows)
+    }
 
+    async fn delete_from_bigquery(&self) -> Result<u64, Error> {
+        let project_id = self.get_client().get_project_id();
+        let table_identifier = self.get_identifier().await?;
+        let query = format!("delete from {} where {}", table_identifier, Self::get_base_where());
+        let query_res = self.run_get_query(&query, project_id).await?;
+        if let Some(snapshot) = self.get_snapshot() {
+            snapshot.clear();
+        }
+        get_affected_rows(&query_res)
+    }
+
+    async fn delete_by_field<T: BigDataValueType<T> + Send>(client: &'a BigqueryClient, field_name: &str, field_value: Option<T>)
+                                                            -> Result<u64, Error> {
+        let field_name = Self::get_field_name(field_name)?;
+        let where_clause = Self::get_where_part(&field_name, field_value.is_none());
         let mut params = vec![];
-        if !(field_value.is_none()) {
+        if field_value.is_some() {
             params.push(Self::get_query_param(&field_name, &field_value));
         }
-        Self::load_by_custom_query(client, &query, params, max_amount).await
+        Self::delete_by_custom_query(client, &where_clause, params).await
     }
 
-    async fn load_by_custom_query(client: &'a BigqueryClient, query: &str, parameters: Vec<QueryParameter>, max_amount: usize)
-                                  -> Result<Vec<TABLE>, Box<dyn Error>> {
+    async fn delete_by_custom_query(client: &'a BigqueryClient, condition: &str, parameters: Vec<QueryParameter>)
+                                    -> Result<u64, Error> {
         let project_id = client.get_project_id();
-        let query_res: google_bigquery2::api::QueryResponse = Self::run_get_query_with_params_on_client(client, &query, parameters, project_id).await?;
+        let table_identifier = Self::get_identifier_from_client(client).await?;
+        let query = format!("delete from {} where {}", table_identifier, condition);
+        let query_res = Self::run_get_query_with_params_on_client(client, &query, parameters, project_id).await?;
+        get_affected_rows(&query_res)
+    }
+
+    async fn load_by_custom_query(client: &'a BigqueryClient, query: &str, parameters: Vec<QueryParameter>, max_amount: usize)
+                                  -> Result<Vec<TABLE>, Error> {
+        load_rows(client, query, parameters, None, false, max_amount).await.map(|loaded| loaded.rows)
+    }
 
-        if let None = &query_res.rows {
-            return Ok(vec![]);
+    async fn load_by_custom_query_with_columns(client: &'a BigqueryClient,
+                                               query: &str,
+                                               parameters: Vec<QueryParameter>,
+                                               columns: &[&str],
+                                               max_amount: usize)
+                                               -> Result<Vec<TABLE>, Error>
+        where TABLE: BigDataTableHasSnapshot {
+        let columns = get_projection::<TABLE, TPK>(columns)?;
+        load_rows(client, query, parameters, Some(&columns), false, max_amount).await.map(|loaded| loaded.rows)
+    }
+
+    async fn load_by_field_lenient<T: BigDataValueType<T> + Send>(client: &'a BigqueryClient, field_name: &str, field_value: Option<T>, max_amount: usize)
+                                                                  -> Result<LenientLoad<TABLE>, Error> {
+        let (query, params) = Self::get_load_by_field_query(client, field_name, field_value, None, Some(max_amount)).await?;
+        Self::load_by_custom_query_lenient(client, &query, params, max_amount).await
+    }
+
+    async fn load_by_custom_query_lenient(client: &'a BigqueryClient, query: &str, parameters: Vec<QueryParameter>, max_amount: usize)
+                                          -> Result<LenientLoad<TABLE>, Error> {
+        load_rows(client, query, parameters, None, true, max_amount).await
+    }
+
+    fn stream_by_field<T: BigDataValueType<T> + Send + 'a>(client: &'a BigqueryClient, field_name: &str, field_value: Option<T>)
+                                                           -> BoxStream<'a, Result<TABLE, Error>>
+        where TABLE: 'a,
+              TPK: 'a {
+        let field_name = field_name.to_string();
+        stream::once(async move { Self::get_load_by_field_query(client, &field_name, field_value, None, None).await })
+            .map_ok(move |(query, params)| Self::stream_by_custom_query(client, &query, params))
+            .try_flatten()
+            .boxed()
+    }
+
+    fn stream_by_custom_query(client: &'a BigqueryClient, query: &str, parameters: Vec<QueryParameter>)
+                              -> BoxStream<'a, Result<TABLE, Error>>
+        where TABLE: 'a,
+              TPK: 'a {
+        let req = QueryRequest {
+            query: Some(query.to_string()),
+            query_parameters: Some(parameters),
+            use_legacy_sql: Some(false),
+            ..Default::default()
+        };
+        client.run_query_pages(req, client.get_project_id())
+            .flat_map(move |page| {
+                let rows = match page.and_then(|page| decode_page(client, page, None)) {
+                    Ok(rows) => rows,
+                    Err(e) => vec![Err(e)],
+                };
+                stream::iter(rows)
+            })
+            .boxed()
+    }
+
+    async fn count(client: &'a BigqueryClient) -> Result<u64, Error> {
+        let table_identifier = Self::get_identifier_from_client(client).await?;
+        let query = format!("select count(*) from {}", table_identifier);
+        get_count(Self::run_get_query_with_params_on_client(client, &query, vec![], client.get_project_id()).await?)
+    }
+
+    async fn count_by_field<T: BigDataValueType<T> + Send>(client: &'a BigqueryClient, field_name: &str, field_value: Option<T>)
+                                                           -> Result<u64, Error> {
+        let field_name = Self::get_field_name(field_name)?;
+        let table_identifier = Self::get_identifier_from_client(client).await?;
+        let query = format!("select count(*) from {} where {}", table_identifier, Self::get_where_part(&field_name, field_value.is_none()));
+        let mut params = vec![];
+        if field_value.is_some() {
+            params.push(Self::get_query_param(&field_name, &field_value));
         }
+        get_count(Self::run_get_query_with_params_on_client(client, &query, params, client.get_project_id()).await?)
+    }
 
-        let rows: Vec<google_bigquery2::api::TableRow> = query_res.rows.unwrap();
-        let mut result: Vec<TABLE> = vec![];
+    async fn exists_by_pk(client: &'a BigqueryClient, pk: TPK) -> Result<bool, Error>
+        where TPK: 'async_trait {
+        let table_identifier = Self::get_identifier_from_client(client).await?;
+        let query = format!("select 1 from {} where {} limit 1", table_identifier, Self::get_base_where());
+        let params = vec![Self::get_query_param(&Self::get_pk_name(), &Some(pk))];
+        let query_res = Self::run_get_query_with_params_on_client(client, &query, params, client.get_project_id()).await?;
+        Ok(query_res.rows.is_some_and(|rows| !rows.is_empty()))
+    }
 
-        let mut index_to_name_mapping: HashMap<String, usize> = get_name_index_mapping(query_res.schema);
+    async fn distinct_values<T: BigDataValueType<T> + Send>(client: &'a BigqueryClient, field_name: &str)
+                                                            -> Result<Vec<Option<T>>, Error> {
+        let field_name = Self::get_field_name(field_name)?;
+        let table_identifier = Self::get_identifier_from_client(client).await?;
+        let query = format!("select distinct {} from {}", field_name, table_identifier);
+        let query_res = Self::run_get_query_with_params_on_client(client, &query, vec![], client.get_project_id()).await?;
+        query_res.rows.unwrap_or_default()
+            .iter()
+            .map(|row| {
+                let value = row.f.as_ref()
+                    .and_then(|cells| cells.first())
+                    .and_then(|cell| cell.v.as_ref());
+                value.map(|value| T::from_bigquery_value(value).map_err(|e| Error::Parse {
+                    struct_name: Self::get_table_name(),
+                    field: field_name.clone(),
+                    value: Some(value.clone()),
+                    message: e.to_string(),
+                })).transpose()
+            })
+            .collect()
+    }
 
-        for row in rows.iter() {
-            for cell in row.f.iter() {
-                //create a new object and write the values to each field
-                let obj = Self::create_from_table_row(client, row, &index_to_name_mapping)?;
-                result.push(obj);
-            }
+    fn query(client: &'a BigqueryClient) -> QueryBuilder<'a, TABLE, TPK>
+        where TABLE: 'a,
+              TPK: 'a {
+        QueryBuilder::new(client)
+    }
+}
+
+/// Runs one `MERGE` of the sources (the selects of the parameters of the rows) and returns the number of affected rows.
+async fn run_merge<'a, TABLE, TPK>(client: &'a BigqueryClient,
+                                   table_identifier: &str,
+                                   sources: &[String],
+                                   update_fields: &[String],
+                                   query_parameters: Vec<QueryParameter>,
+                                   rows: &[TABLE])
+                                   -> Result<u64, Error>
+    where TABLE: BigDataTableBaseConvenience<'a, TABLE, TPK> + Sync,
+          TPK: BigDataValueType<TPK> + FromStr + Debug + Clone {
+    let req = QueryRequest {
+        query: Some(TABLE::get_merge_query(table_identifier, &sources.join(UNION_ALL), update_fields, None)),
+        query_parameters: Some(query_parameters),
+        use_legacy_sql: Some(false),
+        ..Default::default()
+    };
+    let query_res = TABLE::run_query_on_client(client, req, client.get_project_id()).await?;
+    rows.iter().for_each(take_snapshot);
+    get_affected_rows(&query_res)
+}
+
+/// The size of the parameters in the JSON of a request.
+fn get_parameters_size(parameters: &[QueryParameter]) -> usize {
+    parameters.iter()
+        .map(|parameter| serde_json::to_vec(parameter).map_or(0, |json| json.len() + 1))
+        .sum()
+}
+
+/// Loads the rows where the column has one of the values, which are passed as the array parameter `@__{param_name}`.
+async fn load_in<'a, TABLE, TPK, T>(client: &'a BigqueryClient, column: &str, param_name: &str, values: Vec<T>)
+                                    -> Result<Vec<TABLE>, Error>
+    where TABLE: BigDataTableBaseConvenience<'a, TABLE, TPK> + Sync + Send,
+          TPK: BigDataValueType<TPK> + FromStr + Debug + Clone + Send,
+          T: BigDataValueType<T> + Send {
+    if values.is_empty() {
+        return Ok(vec![]);
+    }
+    let table_identifier = TABLE::get_identifier_from_client(client).await?;
+    let query = format!("select {} from {} where {} in unnest(@__{})",
+                        TABLE::get_query_fields_str(), table_identifier, column, param_name);
+    let params = vec![TABLE::get_query_param(param_name, &Some(values))];
+    let query_res = TABLE::run_get_query_with_params_on_client(client, &query, params, client.get_project_id()).await?;
+    decode_rows(client, query_res, None, false).map(|loaded| loaded.rows)
+}
+
+/// The error for a row that did not match its primary key and version: [`Error::NotFound`]
+/// if there is no row with the primary key, [`Error::VersionConflict`] if it has another version.
+async fn get_missing_row_error<'a, TABLE, TPK>(row: &TABLE, table_identifier: String) -> Error
+    where TABLE: BigDataTableBaseConvenience<'a, TABLE, TPK> + Sync,
+          TPK: BigDataValueType<TPK> + FromStr + Debug + Clone {
+    let query = format!("select count(*) from {} where {}", table_identifier, TABLE::get_base_where());
+    let count = match row.run_get_query(&query, row.get_client().get_project_id()).await {
+        Ok(query_res) => get_count(query_res),
+        Err(e) => Err(e),
+    };
+    match count {
+        Ok(0) => Error::NotFound(format!("No row with {} = {:?} in {}", TABLE::get_pk_name(), row.get_pk_value(), table_identifier)),
+        Ok(_) => version_conflict(row, table_identifier),
+        Err(e) => e,
+    }
+}
+
+/// Remembers the current values of the row as the ones in BigQuery, if it has a snapshot.
+fn take_snapshot<'a, TABLE, TPK>(row: &TABLE)
+    where TABLE: BigDataTableBase<'a, TABLE, TPK>,
+          TPK: BigDataValueType<TPK> + FromStr + Debug + Clone {
+    if let Some(snapshot) = row.get_snapshot() {
+        snapshot.set(&row.get_all_query_parameters());
+    }
+}
+
+/// Takes the snapshot of a written row, or restores its previous version if the write failed.
+fn finish_write<'a, TABLE, TPK, T>(row: &mut TABLE, restore_version: Option<RestoreVersion<TABLE>>, result: Result<T, Error>)
+                                   -> Result<T, Error>
+    where TABLE: BigDataTableBase<'a, TABLE, TPK>,
+          TPK: BigDataValueType<TPK> + FromStr + Debug + Clone {
+    match (&result, restore_version) {
+        (Ok(_), _) => take_snapshot(row),
+        (Err(_), Some(restore_version)) => restore_version(row),
+        (Err(_), None) => {}
+    }
+    result
+}
+
+fn version_conflict<'a, TABLE, TPK>(row: &TABLE, table_identifier: String) -> Error
+    where TABLE: BigDataTableBase<'a, TABLE, TPK>,
+          TPK: BigDataValueType<TPK> + FromStr + Debug + Clone {
+    Error::VersionConflict {
+        table: table_identifier,
+        primary_key: format!("{:?}", row.get_pk_value()),
+    }
+}
+
+/// The number of the response of a `select count(*)` query.
+fn get_count(query_res: google_bigquery2::api::QueryResponse) -> Result<u64, Error> {
+    let rows = query_res.rows
+        .ok_or_else(|| Error::InvalidResponse("No rows returned!".to_string()))?;
+    if rows.len() != 1 {
+        return Err(Error::InvalidResponse(format!("Wrong amount of data returned! ({})", rows.len())));
+    }
+    rows[0].f.as_ref()
+        .and_then(|cells| cells.first())
+        .and_then(|cell| cell.v.as_ref())
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| Error::InvalidResponse("The count query did not return a number".to_string()))
+}
+
+/// The `num_dml_affected_rows` of the response of a DML statement.
+fn get_affected_rows(query_res: &google_bigquery2::api::QueryResponse) -> Result<u64, Error> {
+    query_res.num_dml_affected_rows.as_ref()
+        .and_then(|rows| rows.parse().ok())
+        .ok_or_else(|| Error::InvalidResponse("The response of the statement has no number of affected rows".to_string()))
+}
+
+/// Creates a `TABLE` for every row of the result, with only the `columns` if they are set.
+///
+/// If `lenient` is set, rows that can not be decoded are skipped and their errors collected,
+/// otherwise the first error is returned.
+fn decode_rows<'a, TABLE, TPK>(client: &'a BigqueryClient,
+                               query_res: google_bigquery2::api::QueryResponse,
+                               columns: Option<&[String]>,
+                               lenient: bool)
+                               -> Result<LenientLoad<TABLE>, Error>
+    where TABLE: BigDataTableBase<'a, TABLE, TPK>,
+          TPK: BigDataValueType<TPK> + FromStr + Debug + Clone {
+    let mut result = LenientLoad { rows: vec![], errors: vec![] };
+    for row in decode_page(client, query_res, columns)? {
+        match row {
+            Ok(obj) => result.rows.push(obj),
+            Err(e) if lenient => result.errors.push(e),
+            Err(e) => return Err(e),
         }
+    }
+    Ok(result)
+}
 
-        Ok(result)
+/// Runs the query and decodes at most `max_amount` of its rows, only reading as many pages as that takes.
+/// With `lenient`, rows that can not be decoded are collected as errors instead of failing the whole load.
+async fn load_rows<'a, TABLE, TPK>(client: &'a BigqueryClient,
+                                   query: &str,
+                                   parameters: Vec<QueryParameter>,
+                                   columns: Option<&[String]>,
+                                   lenient: bool,
+                                   max_amount: usize)
+                                   -> Result<LenientLoad<TABLE>, Error>
+    where TABLE: BigDataTableBase<'a, TABLE, TPK>,
+          TPK: BigDataValueType<TPK> + FromStr + Debug + Clone {
+    let req = QueryRequest {
+        query: Some(query.to_string()),
+        query_parameters: Some(parameters),
+        use_legacy_sql: Some(false),
+        ..Default::default()
+    };
+    let pages = client.run_query_pages(req, client.get_project_id());
+    futures::pin_mut!(pages);
+    let mut result = LenientLoad { rows: vec![], errors: vec![] };
+    while result.rows.len() < max_amount {
+        let page = match pages.try_next().await? {
+            Some(page) => page,
+            None => break,
+        };
+        for row in decode_page(client, page, columns)? {
+            match row {
+                Ok(_) if result.rows.len() >= max_amount => break,
+                Ok(obj) => result.rows.push(obj),
+                Err(e) if lenient => result.errors.push(e),
+                Err(e) => return Err(e),
+            }
+        }
     }
+    Ok(result)
 }
 
-fn get_name_index_mapping(schema: Option<TableSchema>) -> HashMap<String, usize> {
+/// Decodes every row of the page on its own. Only fails as a whole if the page has rows but no schema.
+fn decode_page<'a, TABLE, TPK>(client: &'a BigqueryClient, page: google_bigquery2::api::QueryResponse, columns: Option<&[String]>)
+                               -> Result<Vec<Result<TABLE, Error>>, Error>
+    where TABLE: BigDataTableBase<'a, TABLE, TPK>,
+          TPK: BigDataValueType<TPK> + FromStr + Debug + Clone {
+    let rows = match page.rows {
+        Some(rows) => rows,
+        None => return Ok(vec![]),
+    };
+    let index_to_name_mapping: HashMap<String, usize> = get_name_index_mapping(page.schema)?;
+
+    //create a new object and write the values to each field
+    Ok(rows.iter()
+        .map(|row| match columns {
+            Some(columns) => create_with_columns(client, row, &index_to_name_mapping, columns),
+            None => TABLE::create_from_table_row(client, row, &index_to_name_mapping),
+        })
+        .collect())
+}
+
+/// Like [`create_from_table_row`](BigDataTableBase::create_from_table_row), but only writes the fields of the `columns`.
+fn create_with_columns<'a, TABLE, TPK>(client: &'a BigqueryClient,
+                                       row: &google_bigquery2::api::TableRow,
+                                       index_to_name_mapping: &HashMap<String, usize>,
+                                       columns: &[String])
+                                       -> Result<TABLE, Error>
+    where TABLE: BigDataTableBase<'a, TABLE, TPK>,
+          TPK: BigDataValueType<TPK> + FromStr + Debug + Clone {
+    let struct_name = TABLE::get_table_name();
+    let pk_name = TABLE::get_pk_name();
+    let pk = match get_table_row_value(row, index_to_name_mapping, &struct_name, &pk_name)? {
+        Some(v) => TPK::from_bigquery_value(v).map_err(|e| Error::Parse {
+            struct_name: struct_name.clone(),
+            field: pk_name.clone(),
+            value: Some(v.to_string()),
+            message: e.to_string(),
+        })?,
+        None => return Err(Error::Parse {
+            struct_name,
+            field: pk_name,
+            value: None,
+            message: "the primary key is NULL".to_string(),
+        }),
+    };
+    let mut res = TABLE::create_with_pk(client, pk);
+    res.write_columns_from_table_row(row, index_to_name_mapping, Some(columns))?;
+    Ok(res)
+}
+
+/// The columns of the fields, the primary key and the `#[version]` field, sorted.
+fn get_projection<'a, TABLE, TPK>(fields: &[&str]) -> Result<Vec<String>, Error>
+    where TABLE: BigDataTableBase<'a, TABLE, TPK>,
+          TPK: BigDataValueType<TPK> + FromStr + Debug + Clone {
+    let mut columns = fields.iter()
+        .map(|field| TABLE::get_field_name(field))
+        .collect::<Result<Vec<String>, Error>>()?;
+    columns.push(TABLE::get_pk_name());
+    columns.extend(TABLE::get_version_name());
+    columns.sort();
+    columns.dedup();
+    Ok(columns)
+}
+
+fn get_name_index_mapping(schema: Option<TableSchema>) -> Result<HashMap<String, usize>, Error> {
+    let fields = schema.and_then(|schema| schema.fields)
+        .ok_or_else(|| Error::InvalidResponse("The query result has no schema".to_string()))?;
     let mut index_to_name_mapping: HashMap<String, usize> = HashMap::new();
-    for (i, x) in schema.unwrap().fields.unwrap().iter().enumerate() {
-        index_to_name_mapping.insert(x.name.clone().unwrap(), i);
+    for (i, x) in fields.iter().enumerate() {
+        if let Some(name) = &x.name {
+            index_to_name_mapping.insert(name.clone(), i);
+        }
     }
-    index_to_name_mapping
+    Ok(index_to_name_mapping)
 }
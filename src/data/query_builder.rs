@@ -0,0 +1,333 @@
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use google_bigquery2::api::QueryParameter;
+
+use crate::client::BigqueryClient;
+use crate::data::{BigDataTable, BigDataTableBaseConvenience, TableField, TypedTableField};
+use crate::error::Error;
+use crate::utils::BigDataValueType;
+
+/// The direction of an `ORDER BY`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Conjunction {
+    And,
+    Or,
+}
+
+/// A condition on the fields of a table. The parameters are named when the query is built.
+#[derive(Debug, Clone)]
+enum Condition {
+    Compare { field: String, operator: &'static str, value: QueryParameter },
+    Between { field: String, low: QueryParameter, high: QueryParameter },
+    In { field: String, values: Vec<QueryParameter> },
+    Like { field: String, pattern: QueryParameter },
+    IsNull { field: String, negated: bool },
+    Group { conjunction: Conjunction, conditions: Vec<Condition> },
+}
+
+/// Conditions on the fields of `TABLE` that all ([`Filter::all`]) or any ([`Filter::any`]) have to hold.
+///
+/// Fields are [`Column`](crate::Column)s, so the values of a comparison have to have the type of the field.
+/// Names of fields (like in [`load_by_field`](BigDataTable::load_by_field)) can be used for conditions without values
+/// and, wrapped in an [`UntypedField`](crate::UntypedField), for comparisons that are not type checked.
+/// Names are checked when the query is built.
+/// Values are bound as query parameters (see [`get_query_param`](BigDataTableBaseConvenience::get_query_param)).
+/// ```ignore
+/// let filter = Filter::all()
+///     .eq(Infos::COLUMNS.info3, "cc".to_string())
+///     .group(Filter::any().lt(UntypedField::new("row_id"), 10).is_null("info1"));
+/// ```
+pub struct Filter<'a, TABLE, TPK> {
+    conjunction: Conjunction,
+    conditions: Vec<Condition>,
+    table: PhantomData<fn() -> (TABLE, TPK)>,
+    lifetime: PhantomData<&'a ()>,
+}
+
+impl<'a, TABLE, TPK> Filter<'a, TABLE, TPK>
+    where TABLE: BigDataTableBaseConvenience<'a, TABLE, TPK> + Sync,
+          TPK: BigDataValueType<TPK> + FromStr + Debug + Clone {
+    /// A filter that holds if all of its conditions hold (or if it has none).
+    pub fn all() -> Self {
+        Self::new(Conjunction::And)
+    }
+
+    /// A filter that holds if any of its conditions holds (and never if it has none).
+    pub fn any() -> Self {
+        Self::new(Conjunction::Or)
+    }
+
+    fn new(conjunction: Conjunction) -> Self {
+        Self {
+            conjunction,
+            conditions: vec![],
+            table: PhantomData,
+            lifetime: PhantomData,
+        }
+    }
+
+    fn with(mut self, condition: Condition) -> Self {
+        self.conditions.push(condition);
+        self
+    }
+
+    fn compare<T: BigDataValueType<T>>(self, field: impl TypedTableField<TABLE, T>, operator: &'static str, value: T) -> Self {
+        self.with(Condition::Compare { field: field.field_name().to_string(), operator, value: get_param::<TABLE, TPK, T>(value) })
+    }
+
+    pub fn eq<T: BigDataValueType<T>>(self, field: impl TypedTableField<TABLE, T>, value: T) -> Self {
+        self.compare(field, "=", value)
+    }
+
+    pub fn ne<T: BigDataValueType<T>>(self, field: impl TypedTableField<TABLE, T>, value: T) -> Self {
+        self.compare(field, "!=", value)
+    }
+
+    pub fn lt<T: BigDataValueType<T>>(self, field: impl TypedTableField<TABLE, T>, value: T) -> Self {
+        self.compare(field, "<", value)
+    }
+
+    pub fn le<T: BigDataValueType<T>>(self, field: impl TypedTableField<TABLE, T>, value: T) -> Self {
+        self.compare(field, "<=", value)
+    }
+
+    pub fn gt<T: BigDataValueType<T>>(self, field: impl TypedTableField<TABLE, T>, value: T) -> Self {
+        self.compare(field, ">", value)
+    }
+
+    pub fn ge<T: BigDataValueType<T>>(self, field: impl TypedTableField<TABLE, T>, value: T) -> Self {
+        self.compare(field, ">=", value)
+    }
+
+    /// The field is between `low` and `high`, both included.
+    pub fn between<T: BigDataValueType<T>>(self, field: impl TypedTableField<TABLE, T>, low: T, high: T) -> Self {
+        self.with(Condition::Between {
+            field: field.field_name().to_string(),
+            low: get_param::<TABLE, TPK, T>(low),
+            high: get_param::<TABLE, TPK, T>(high),
+        })
+    }
+
+    /// The field has one of the values. Never holds for an empty list.
+    pub fn is_in<T: BigDataValueType<T>>(self, field: impl TypedTableField<TABLE, T>, values: impl IntoIterator<Item=T>) -> Self {
+        self.with(Condition::In {
+            field: field.field_name().to_string(),
+            values: values.into_iter().map(get_param::<TABLE, TPK, T>).collect(),
+        })
+    }
+
+    /// The field matches the `LIKE` pattern (`%` for any characters, `_` for a single one).
+    pub fn like(self, field: impl TypedTableField<TABLE, String>, pattern: &str) -> Self {
+        self.with(Condition::Like { field: field.field_name().to_string(), pattern: get_param::<TABLE, TPK, String>(pattern.to_string()) })
+    }
+
+    pub fn is_null(self, field: impl TableField<TABLE>) -> Self {
+        self.with(Condition::IsNull { field: field.field_name().to_string(), negated: false })
+    }
+
+    pub fn is_not_null(self, field: impl TableField<TABLE>) -> Self {
+        self.with(Condition::IsNull { field: field.field_name().to_string(), negated: true })
+    }
+
+    /// Adds another filter as one condition, e.g. an [`any`](Self::any) group to an [`all`](Self::all) filter.
+    pub fn group(self, filter: Self) -> Self {
+        self.with(Condition::Group { conjunction: filter.conjunction, conditions: filter.conditions })
+    }
+
+    /// The condition as SQL, `None` if the filter has no conditions.
+    /// Its parameters are appended to `parameters`.
+    fn to_sql(&self, parameters: &mut Vec<QueryParameter>) -> Result<Option<String>, Error> {
+        if self.conditions.is_empty() {
+            return Ok(None);
+        }
+        to_sql::<TABLE, TPK>(self.conjunction, &self.conditions, parameters).map(Some)
+    }
+}
+
+impl<'a, TABLE, TPK> Clone for Filter<'a, TABLE, TPK> {
+    fn clone(&self) -> Self {
+        Self {
+            conjunction: self.conjunction,
+            conditions: self.conditions.clone(),
+            table: PhantomData,
+            lifetime: PhantomData,
+        }
+    }
+}
+
+impl<'a, TABLE, TPK> Debug for Filter<'a, TABLE, TPK> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Filter")
+            .field("conjunction", &self.conjunction)
+            .field("conditions", &self.conditions)
+            .finish()
+    }
+}
+
+/// The parameter of a value, without a name yet.
+fn get_param<'a, TABLE, TPK, T>(value: T) -> QueryParameter
+    where TABLE: BigDataTableBaseConvenience<'a, TABLE, TPK> + Sync,
+          TPK: BigDataValueType<TPK> + FromStr + Debug + Clone,
+          T: BigDataValueType<T> {
+    TABLE::get_query_param("", &Some(value))
+}
+
+fn to_sql<'a, TABLE, TPK>(conjunction: Conjunction, conditions: &[Condition], parameters: &mut Vec<QueryParameter>)
+                          -> Result<String, Error>
+    where TABLE: BigDataTableBaseConvenience<'a, TABLE, TPK> + Sync,
+          TPK: BigDataValueType<TPK> + FromStr + Debug + Clone {
+    let mut parts = vec![];
+    for condition in conditions {
+        let part = match condition {
+            Condition::Compare { field, operator, value } => {
+                let column = TABLE::get_field_name(field)?;
+                format!("{} {} {}", column, operator, bind(parameters, &column, value))
+            }
+            Condition::Between { field, low, high } => {
+                let column = TABLE::get_field_name(field)?;
+                format!("{} between {} and {}", column, bind(parameters, &column, low), bind(parameters, &column, high))
+            }
+            Condition::In { field, values } if values.is_empty() => {
+                TABLE::get_field_name(field)?;
+                "false".to_string()
+            }
+            Condition::In { field, values } => {
+                let column = TABLE::get_field_name(field)?;
+                let values: Vec<String> = values.iter().map(|value| bind(parameters, &column, value)).collect();
+                format!("{} in ({})", column, values.join(", "))
+            }
+            Condition::Like { field, pattern } => {
+                let column = TABLE::get_field_name(field)?;
+                format!("{} like {}", column, bind(parameters, &column, pattern))
+            }
+            Condition::IsNull { field, negated: false } => format!("{} is null", TABLE::get_field_name(field)?),
+            Condition::IsNull { field, negated: true } => format!("{} is not null", TABLE::get_field_name(field)?),
+            Condition::Group { conjunction: Conjunction::And, conditions } if conditions.is_empty() => "true".to_string(),
+            Condition::Group { conjunction: Conjunction::Or, conditions } if conditions.is_empty() => "false".to_string(),
+            Condition::Group { conjunction, conditions } => format!("({})", to_sql::<TABLE, TPK>(*conjunction, conditions, parameters)?),
+        };
+        parts.push(part);
+    }
+    let separator = match conjunction {
+        Conjunction::And => " and ",
+        Conjunction::Or => " or ",
+    };
+    Ok(parts.join(separator))
+}
+
+/// Adds the parameter and returns its placeholder. The parameter is named after its column and position,
+/// so the same column can be used more than once.
+fn bind(parameters: &mut Vec<QueryParameter>, column: &str, parameter: &QueryParameter) -> String {
+    let name = format!("__{}_{}", column, parameters.len());
+    parameters.push(QueryParameter { name: Some(name.clone()), ..parameter.clone() });
+    format!("@{}", name)
+}
+
+/// Loads the rows of `TABLE` that match a [`Filter`], in the given order and page.
+///
+/// Created with [`BigDataTable::query`].
+/// ```ignore
+/// let rows = Infos::query(&client)
+///     .filter(Filter::all().gt(Infos::COLUMNS.row_id, 5).like(Infos::COLUMNS.info3, "c%"))
+///     .order_by(Infos::COLUMNS.row_id, Order::Desc)
+///     .limit(10)
+///     .offset(20)
+///     .load().await?;
+/// ```
+pub struct QueryBuilder<'a, TABLE, TPK> {
+    client: &'a BigqueryClient,
+    filter: Filter<'a, TABLE, TPK>,
+    order_by: Vec<(String, Order)>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+}
+
+impl<'a, TABLE, TPK> QueryBuilder<'a, TABLE, TPK>
+    where TABLE: BigDataTable<'a, TABLE, TPK> + Sync + Send + 'a,
+          TPK: BigDataValueType<TPK> + FromStr + Debug + Clone + Send + 'a,
+          <TPK as FromStr>::Err: Debug {
+    pub fn new(client: &'a BigqueryClient) -> Self {
+        Self {
+            client,
+            filter: Filter::all(),
+            order_by: vec![],
+            limit: None,
+            offset: None,
+        }
+    }
+
+    /// Only loads the rows that match the filter (and the filters of earlier calls).
+    pub fn filter(mut self, filter: Filter<'a, TABLE, TPK>) -> Self {
+        self.filter = self.filter.group(filter);
+        self
+    }
+
+    /// Orders the rows by the field, after the fields of earlier calls.
+    pub fn order_by(mut self, field: impl TableField<TABLE>, order: Order) -> Self {
+        self.order_by.push((field.field_name().to_string(), order));
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Skips the first rows. Needs a [`limit`](Self::limit), like in BigQuery.
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// The query and its parameters.
+    pub async fn build(&self) -> Result<(String, Vec<QueryParameter>), Error> {
+        let table_identifier = TABLE::get_identifier_from_client(self.client).await?;
+        let mut parameters = vec![];
+        let mut query = format!("select {} from {}", TABLE::get_query_fields_str(), table_identifier);
+        if let Some(condition) = self.filter.to_sql(&mut parameters)? {
+            query = format!("{} where {}", query, condition);
+        }
+        if !self.order_by.is_empty() {
+            let mut order_by = vec![];
+            for (field, order) in self.order_by.iter() {
+                let direction = match order {
+                    Order::Asc => "asc",
+                    Order::Desc => "desc",
+                };
+                order_by.push(format!("{} {}", TABLE::get_field_name(field)?, direction));
+            }
+            query = format!("{} order by {}", query, order_by.join(", "));
+        }
+        match (self.limit, self.offset) {
+            (Some(limit), Some(offset)) => query = format!("{} limit {} offset {}", query, limit, offset),
+            (Some(limit), None) => query = format!("{} limit {}", query, limit),
+            (None, Some(_)) => return Err(Error::Other("An offset needs a limit".to_string())),
+            (None, None) => {}
+        }
+        Ok((query, parameters))
+    }
+
+    pub async fn load(self) -> Result<Vec<TABLE>, Error> {
+        let (query, parameters) = self.build().await?;
+        TABLE::load_by_custom_query(self.client, &query, parameters, self.limit.unwrap_or(usize::MAX)).await
+    }
+
+    /// Like [`load`](Self::load), but returns the rows as a stream
+    /// (see [`stream_by_custom_query`](BigDataTable::stream_by_custom_query)).
+    pub fn stream(self) -> BoxStream<'a, Result<TABLE, Error>> {
+        let client = self.client;
+        stream::once(async move { self.build().await })
+            .map_ok(move |(query, parameters)| TABLE::stream_by_custom_query(client, &query, parameters))
+            .try_flatten()
+            .boxed()
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Formatter};
+use std::sync::Mutex;
+
+use google_bigquery2::api::QueryParameter;
+
+/// The values of a row as they were last loaded from or saved to BigQuery.
+///
+/// Add a field of this type with the `#[snapshot]` attribute to a table struct to enable dirty tracking:
+/// updates then only set the fields that changed since, and saving a row without changes runs no job at all.
+/// Rows that were not loaded (e.g. created with `create_with_pk`) have an empty snapshot and are
+/// always written completely.
+///
+/// It also records which fields were loaded if only some of them were
+/// (see [`load_by_field_with_columns`](crate::BigDataTable::load_by_field_with_columns)).
+/// The values of the other fields are not known, so they are only written if they were changed
+/// or [marked as changed](Self::mark_changed). The latter is needed to write a value a field
+/// already had after the load, e.g. to set a field that was not loaded to its default value.
+#[derive(Default)]
+pub struct Snapshot {
+    /// The values of the query parameters of the loaded fields by parameter name, `None` until the row is loaded or saved.
+    values: Mutex<Option<HashMap<String, serde_json::Value>>>,
+    /// The columns that were loaded, `None` if all of them were.
+    loaded_fields: Mutex<Option<Vec<String>>>,
+    /// The values the fields that were not loaded had after the load, by parameter name.
+    unloaded_values: Mutex<HashMap<String, serde_json::Value>>,
+    /// The parameter names of the fields that were marked as changed.
+    marked_changed: Mutex<HashSet<String>>,
+}
+
+impl Snapshot {
+    /// Remembers the values of the parameters (as returned by `get_all_query_parameters`)
+    /// as the ones in BigQuery. Only the ones of the [loaded fields](Self::get_loaded_fields) are,
+    /// so [`set_loaded_fields`](Self::set_loaded_fields) has to be called first.
+    pub fn set(&self, parameters: &[QueryParameter]) {
+        let loaded_fields = self.get_loaded_fields();
+        let is_loaded = |name: &str| loaded_fields.as_ref()
+            .is_none_or(|fields| fields.iter().any(|field| name.strip_prefix("__") == Some(field.as_str())));
+        let (values, unloaded_values): (HashMap<_, _>, HashMap<_, _>) = parameters.iter()
+            .filter_map(|p| Some((p.name.clone()?, serde_json::to_value(&p.parameter_value).ok()?)))
+            .partition(|(name, _)| is_loaded(name));
+        *self.values.lock().unwrap() = Some(values);
+        *self.unloaded_values.lock().unwrap() = unloaded_values;
+        self.marked_changed.lock().unwrap().clear();
+    }
+
+    /// Forgets the values, so the next save writes all fields again.
+    pub fn clear(&self) {
+        *self.values.lock().unwrap() = None;
+        *self.loaded_fields.lock().unwrap() = None;
+        self.unloaded_values.lock().unwrap().clear();
+        self.marked_changed.lock().unwrap().clear();
+    }
+
+    /// Remembers which columns were loaded, `None` for all of them.
+    pub fn set_loaded_fields(&self, loaded_fields: Option<Vec<String>>) {
+        *self.loaded_fields.lock().unwrap() = loaded_fields;
+    }
+
+    /// Lets the next save write the column even if its value did not change, until the row is saved.
+    pub fn mark_changed(&self, column: &str) {
+        self.marked_changed.lock().unwrap().insert(format!("__{}", column));
+    }
+
+    /// The columns that were loaded, `None` if all of them were (or the row was not loaded).
+    /// The other fields have their default values.
+    pub fn get_loaded_fields(&self) -> Option<Vec<String>> {
+        self.loaded_fields.lock().unwrap().clone()
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.values.lock().unwrap().is_some()
+    }
+
+    /// The names of the parameters whose values differ from the snapshot or that were
+    /// [marked as changed](Self::mark_changed), or all of them if there is no snapshot.
+    pub fn get_changed(&self, parameters: &[QueryParameter]) -> Vec<String> {
+        let values = self.values.lock().unwrap();
+        let unloaded_values = self.unloaded_values.lock().unwrap();
+        let marked_changed = self.marked_changed.lock().unwrap();
+        parameters.iter()
+            .filter(|p| match (values.as_ref(), &p.name) {
+                (Some(_), Some(name)) if marked_changed.contains(name) => true,
+                (Some(values), Some(name)) => {
+                    let value = serde_json::to_value(&p.parameter_value).ok();
+                    values.get(name).or_else(|| unloaded_values.get(name)) != value.as_ref()
+                }
+                _ => true,
+            })
+            .filter_map(|p| p.name.clone())
+            .collect()
+    }
+}
+
+impl Clone for Snapshot {
+    fn clone(&self) -> Self {
+        Self {
+            values: Mutex::new(self.values.lock().unwrap().clone()),
+            loaded_fields: Mutex::new(self.get_loaded_fields()),
+            unloaded_values: Mutex::new(self.unloaded_values.lock().unwrap().clone()),
+            marked_changed: Mutex::new(self.marked_changed.lock().unwrap().clone()),
+        }
+    }
+}
+
+impl Debug for Snapshot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Snapshot")
+            .field("is_set", &self.is_set())
+            .field("loaded_fields", &self.get_loaded_fields())
+            .finish()
+    }
+}
@@ -0,0 +1,159 @@
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+/// The errors returned by the [`BigqueryClient`](crate::BigqueryClient) and the table traits.
+#[derive(Debug)]
+pub enum Error {
+    /// A row that was expected to exist does not exist.
+    NotFound(String),
+    /// More than one row (or an already existing row) has the primary key.
+    DuplicatePrimaryKey {
+        table: String,
+        primary_key: String,
+    },
+    /// BigQuery answered with an error status.
+    ///
+    /// `reason`, `message` and `location` are taken from the first entry of `error.errors`
+    /// in the response body if there is one (e.g. `notFound`, `invalidQuery`, `rateLimitExceeded`).
+    Http {
+        status: u16,
+        reason: Option<String>,
+        message: String,
+        location: Option<String>,
+    },
+    /// A row returned by BigQuery could not be converted to the struct, because the column of a field
+    /// is missing, or its value is NULL for a required field or could not be converted to the type of the field.
+    Parse {
+        struct_name: String,
+        field: String,
+        /// The raw value, `None` if the value is NULL or the column is missing.
+        value: Option<String>,
+        message: String,
+    },
+    /// The row was changed by someone else since it was loaded: its `#[version]` column
+    /// does not have the version the row was loaded with anymore.
+    VersionConflict {
+        table: String,
+        primary_key: String,
+    },
+    /// The field is not part of the table.
+    UnknownField {
+        field: String,
+        available_fields: Vec<String>,
+    },
+    /// No access token could be obtained for the credentials.
+    Auth(String),
+    /// The request could not be sent or its response could not be read (connection errors, timeouts, ...).
+    Request(Box<google_bigquery2::Error>),
+    /// The query job did not complete within the wait timeout of the client.
+    JobTimeout {
+        job_id: String,
+        waited: Duration,
+    },
+    /// BigQuery answered with something that does not fit the request, e.g. a query without a schema.
+    InvalidResponse(String),
+    /// Anything else, e.g. an incomplete client configuration.
+    Other(String),
+}
+
+impl Error {
+    /// Builds an [`Error::Http`] from an error response body
+    /// (`{"error": {"code": .., "message": .., "errors": [{"reason": .., "message": .., "location": ..}]}}`).
+    pub(crate) fn from_error_response(body: &serde_json::Value) -> Self {
+        let error = &body["error"];
+        let details = &error["errors"][0];
+        let message = error["message"].as_str()
+            .or_else(|| details["message"].as_str())
+            .map(|message| message.to_string())
+            .unwrap_or_else(|| body.to_string());
+        Error::Http {
+            status: error["code"].as_u64().unwrap_or_default() as u16,
+            reason: details["reason"].as_str().map(|reason| reason.to_string()),
+            message,
+            location: details["location"].as_str().map(|location| location.to_string()),
+        }
+    }
+
+    /// The inverse of [`from_error_response`](Self::from_error_response), for errors of type [`Error::Http`].
+    pub(crate) fn to_error_response(&self) -> Option<serde_json::Value> {
+        match self {
+            Error::Http { status, reason, message, location } => Some(serde_json::json!({
+                "error": {
+                    "code": status,
+                    "message": message,
+                    "errors": [{"reason": reason, "message": message, "location": location}],
+                }
+            })),
+            _ => None,
+        }
+    }
+
+    /// Whether the error is a timeout or a connection problem.
+    pub fn is_transport_error(&self) -> bool {
+        match self {
+            Error::Request(e) => matches!(**e, google_bigquery2::Error::HttpError(_) | google_bigquery2::Error::Io(_)),
+            _ => false,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotFound(message) => write!(f, "Not found: {}", message),
+            Error::DuplicatePrimaryKey { table, primary_key } =>
+                write!(f, "Duplicate primary key {} in table {}", primary_key, table),
+            Error::Http { status, reason, message, location } => {
+                write!(f, "BigQuery returned status {}", status)?;
+                if let Some(reason) = reason {
+                    write!(f, " ({})", reason)?;
+                }
+                write!(f, ": {}", message)?;
+                if let Some(location) = location {
+                    write!(f, " at {}", location)?;
+                }
+                Ok(())
+            }
+            Error::Parse { struct_name, field, value: Some(value), message } =>
+                write!(f, "Could not parse field {}.{} with value {}: {}", struct_name, field, value, message),
+            Error::Parse { struct_name, field, value: None, message } =>
+                write!(f, "Could not parse field {}.{}: {}", struct_name, field, message),
+            Error::VersionConflict { table, primary_key } =>
+                write!(f, "The row with primary key {} in table {} was changed since it was loaded", primary_key, table),
+            Error::UnknownField { field, available_fields } =>
+                write!(f, "Field not found {}\nPlease choose one of the following: {}", field, available_fields.join(", ")),
+            Error::Auth(message) => write!(f, "Authentication failed: {}", message),
+            Error::Request(e) => write!(f, "Request failed: {}", e),
+            Error::JobTimeout { job_id, waited } =>
+                write!(f, "Job {} did not complete within {:?}", job_id, waited),
+            Error::InvalidResponse(message) => write!(f, "Invalid response: {}", message),
+            Error::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Request(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<google_bigquery2::Error> for Error {
+    fn from(e: google_bigquery2::Error) -> Self {
+        use google_bigquery2::Error as ApiError;
+        match e {
+            ApiError::BadRequest(body) => Error::from_error_response(&body),
+            ApiError::Failure(response) => Error::Http {
+                status: response.status().as_u16(),
+                reason: None,
+                message: response.status().canonical_reason().unwrap_or_default().to_string(),
+                location: None,
+            },
+            ApiError::MissingToken(e) => Error::Auth(e.to_string()),
+            e => Error::Request(Box::new(e)),
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use async_trait::async_trait;
+use google_bigquery2::api::{GetQueryResultsResponse, QueryRequest, QueryResponse};
+use google_bigquery2::Bigquery;
+use google_bigquery2::hyper::client::HttpConnector;
+use google_bigquery2::hyper_rustls::HttpsConnector;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// Sends the queries of a [`BigqueryClient`](crate::BigqueryClient) to BigQuery (or anything else).
+///
+/// The client applies its query options, timeout and retry policy before handing
+/// the request to the executor, so implementations only have to run the request once.
+/// Errors of type [`Error::Http`] and [`Error::Request`] are checked against the retry policy.
+#[async_trait]
+pub trait QueryExecutor: Send + Sync {
+    async fn query(&self, req: QueryRequest, project_id: &str) -> Result<QueryResponse, Error>;
+
+    /// Waits for a query job to complete or fetches the next page of its results (`jobs.getQueryResults`).
+    ///
+    /// Only needed for executors whose query responses can be incomplete or paged.
+    async fn get_query_results(&self, req: GetQueryResultsRequest, _project_id: &str) -> Result<GetQueryResultsResponse, Error> {
+        Err(Error::Other(format!("This executor can not fetch the results of job {}", req.job_id)))
+    }
+}
+
+/// The parameters of a `jobs.getQueryResults` call.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GetQueryResultsRequest {
+    pub job_id: String,
+    pub location: Option<String>,
+    /// The page to fetch, `None` for the first one.
+    pub page_token: Option<String>,
+    /// How long the server may wait for the job to complete before answering.
+    pub timeout_ms: Option<u32>,
+}
+
+#[async_trait]
+impl QueryExecutor for Bigquery<HttpsConnector<HttpConnector>> {
+    async fn query(&self, req: QueryRequest, project_id: &str) -> Result<QueryResponse, Error> {
+        let (res, query_res) = self.jobs().query(req, project_id).doit().await?;
+        if res.status() != 200 {
+            return Err(Error::Http {
+                status: res.status().as_u16(),
+                reason: None,
+                message: "Wrong status code returned!".to_string(),
+                location: None,
+            });
+        }
+        Ok(query_res)
+    }
+
+    async fn get_query_results(&self, req: GetQueryResultsRequest, project_id: &str) -> Result<GetQueryResultsResponse, Error> {
+        let mut call = self.jobs().get_query_results(project_id, &req.job_id);
+        if let Some(location) = &req.location {
+            call = call.location(location);
+        }
+        if let Some(page_token) = &req.page_token {
+            call = call.page_token(page_token);
+        }
+        if let Some(timeout_ms) = req.timeout_ms {
+            call = call.timeout_ms(timeout_ms);
+        }
+        let (res, results) = call.doit().await?;
+        if res.status() != 200 {
+            return Err(Error::Http {
+                status: res.status().as_u16(),
+                reason: None,
+                message: "Wrong status code returned!".to_string(),
+                location: None,
+            });
+        }
+        Ok(results)
+    }
+}
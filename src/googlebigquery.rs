@@ -1,13 +1,16 @@
-use std::error::Error;
-
-use google_bigquery2::{hyper, hyper_rustls, oauth2, Bigquery};
+use google_bigquery2::{hyper, hyper_rustls, Bigquery};
 // use google_bigquery2::api::QueryRequest;
 use google_bigquery2::hyper::client::HttpConnector;
 use google_bigquery2::hyper_rustls::HttpsConnector;
 
-pub async fn get_client<S: Into<String>>(
-    service_account_path: Option<S>,
-) -> Result<Bigquery<HttpsConnector<HttpConnector>>, Box<dyn Error>> {
+use crate::credentials::{self, Credentials};
+use crate::error::Error;
+
+pub async fn get_client(
+    credentials: Credentials,
+    root_url: Option<String>,
+    user_agent: Option<String>,
+) -> Result<Bigquery<HttpsConnector<HttpConnector>>, Error> {
     let hyper_client = hyper::Client::builder().build(
         hyper_rustls::HttpsConnectorBuilder::new()
             .with_native_roots()
@@ -16,18 +19,19 @@ pub async fn get_client<S: Into<String>>(
             .enable_http2()
             .build(),
     );
-    let service_account_path = match service_account_path {
-        None => "auth/service_account2.json".to_string(),
-        Some(s) => s.into(),
-    };
-    let secret = oauth2::read_service_account_key(&service_account_path)
-        .await
-        .expect(format!("Failed to read service account key from file. {}", service_account_path).as_str());
-    let auth = oauth2::ServiceAccountAuthenticator::builder(secret)
-        .build()
-        .await
-        .expect("Failed to authenticate with service account key.");
-    let client: Bigquery<HttpsConnector<HttpConnector>> = Bigquery::new(hyper_client, auth);
+    let auth = credentials::get_authenticator(credentials).await?;
+    let mut client: Bigquery<HttpsConnector<HttpConnector>> = Bigquery::new(hyper_client, auth);
+    if let Some(root_url) = root_url {
+        let root_url = match root_url.ends_with('/') {
+            true => root_url,
+            false => format!("{}/", root_url),
+        };
+        client.base_url(format!("{}bigquery/v2/", root_url));
+        client.root_url(root_url);
+    }
+    if let Some(user_agent) = user_agent {
+        client.user_agent(user_agent);
+    }
 
     Ok(client)
 }
@@ -1,17 +1,34 @@
 #![allow(unused)]
 #![allow(incomplete_features)]
 
+// lets the code generated by the derive macros refer to `google_bigquery::..` inside this crate too
+extern crate self as google_bigquery;
+
 pub use google_bigquery_derive::HasBigQueryClient as HasBigQueryClientDerive;
 pub use google_bigquery_derive::BigDataTable as BigDataTableDerive;
 // pub use google_bigquery_derive::MyDerive;
 
-pub use client::{BigqueryClient, HasBigQueryClient};
-pub use data::{BigDataTable, BigDataTableBase, BigDataTableBaseConvenience, BigDataTableHasPk};
+pub use client::{BigqueryClient, BigqueryClientBuilder, HasBigQueryClient, QueryOptions, SaveMode};
+pub use credentials::Credentials;
+pub use error::Error;
+pub use executor::{GetQueryResultsRequest, QueryExecutor};
+pub use query_result::{QueryResult, QueryResultRow};
+pub use retry::RetryPolicy;
+pub use row_deserializer::{from_table_row, TableRowDeserializer};
+pub use data::{BigDataTable, BigDataTableBase, BigDataTableBaseConvenience, BigDataTableHasPk, BigDataTableHasSnapshot, Column, Filter, LenientLoad, Order, QueryBuilder,
+               RestoreVersion, Snapshot, TableField, TypedTableField, UntypedField};
 
 pub mod client;
+mod credentials;
+mod error;
 mod googlebigquery;
 mod data;
+mod executor;
+mod query_result;
+mod retry;
+mod row_deserializer;
 pub mod utils;
+pub mod testing;
 
 // pub fn add(left: usize, right: usize) -> usize {
 //     left + right
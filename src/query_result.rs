@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::ops::Index;
+use std::sync::Arc;
+
+use google_bigquery2::api::{QueryResponse, TableRow, TableSchema};
+use serde::de::DeserializeOwned;
+
+use crate::error::Error;
+use crate::row_deserializer::from_table_row;
+use crate::utils::{ConvertValueToBigqueryParamValue, get_table_row_value};
+
+/// The name used for the struct in the [`Error::Parse`] errors of a [`QueryResultRow`].
+const STRUCT_NAME: &str = "QueryResult";
+
+/// The schema and rows of a query run with [`BigqueryClient::query`](crate::BigqueryClient::query).
+#[derive(Debug, Clone)]
+pub struct QueryResult {
+    schema: TableSchema,
+    rows: Vec<QueryResultRow>,
+}
+
+impl QueryResult {
+    /// Fails with [`Error::InvalidResponse`] if the response has rows but no schema.
+    pub(crate) fn from_response(response: QueryResponse) -> Result<Self, Error> {
+        let rows = response.rows.unwrap_or_default();
+        let schema = match response.schema {
+            Some(schema) => schema,
+            None if rows.is_empty() => TableSchema::default(),
+            None => return Err(Error::InvalidResponse("The query result has no schema".to_string())),
+        };
+        let mut columns = HashMap::new();
+        for (i, field) in schema.fields.iter().flatten().enumerate() {
+            if let Some(name) = &field.name {
+                columns.insert(name.clone(), i);
+            }
+        }
+        let columns = Arc::new(columns);
+        let rows = rows.into_iter()
+            .map(|row| QueryResultRow { columns: columns.clone(), row })
+            .collect();
+        Ok(Self { schema, rows })
+    }
+
+    pub fn schema(&self) -> &TableSchema {
+        &self.schema
+    }
+
+    /// The names of the columns in the order of the schema.
+    pub fn column_names(&self) -> Vec<&str> {
+        self.schema.fields.iter().flatten()
+            .map(|field| field.name.as_deref().unwrap_or_default())
+            .collect()
+    }
+
+    /// Reads every row into a `T` (see [`TableRowDeserializer`](crate::TableRowDeserializer)).
+    pub fn deserialize_rows<T: DeserializeOwned>(&self) -> Result<Vec<T>, Error> {
+        self.rows.iter()
+            .map(|row| from_table_row(&row.row, &self.schema))
+            .collect()
+    }
+
+    pub fn rows(&self) -> &[QueryResultRow] {
+        &self.rows
+    }
+
+    pub fn into_rows(self) -> Vec<QueryResultRow> {
+        self.rows
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+}
+
+impl IntoIterator for QueryResult {
+    type Item = QueryResultRow;
+    type IntoIter = std::vec::IntoIter<QueryResultRow>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.rows.into_iter()
+    }
+}
+
+/// A row of a [`QueryResult`], whose values can be read by the name or the index of their column.
+///
+/// Indexing a row (`row["name"]` or `row[0]`) returns the raw value (`None` for NULL)
+/// and panics if there is no such column.
+#[derive(Debug, Clone)]
+pub struct QueryResultRow {
+    columns: Arc<HashMap<String, usize>>,
+    row: TableRow,
+}
+
+impl QueryResultRow {
+    /// The value of the column, converted to `T`.
+    ///
+    /// Fails with [`Error::Parse`] if there is no such column, if the value is NULL
+    /// (use [`get_opt`](Self::get_opt) for nullable columns) or if it can not be converted.
+    pub fn get<T: ConvertValueToBigqueryParamValue>(&self, column: &str) -> Result<T, Error> {
+        let value = self.get_raw(column)?;
+        parse(column, value)
+    }
+
+    /// Like [`get`](Self::get), but returns `None` for NULL.
+    pub fn get_opt<T: ConvertValueToBigqueryParamValue>(&self, column: &str) -> Result<Option<T>, Error> {
+        match self.get_raw(column)? {
+            Some(_) => self.get(column).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Like [`get`](Self::get) for the column at `index` (in the order of the schema).
+    pub fn get_at<T: ConvertValueToBigqueryParamValue>(&self, index: usize) -> Result<T, Error> {
+        let value = self.get_raw_at(index)?;
+        parse(&index.to_string(), value)
+    }
+
+    /// Like [`get_opt`](Self::get_opt) for the column at `index` (in the order of the schema).
+    pub fn get_opt_at<T: ConvertValueToBigqueryParamValue>(&self, index: usize) -> Result<Option<T>, Error> {
+        match self.get_raw_at(index)? {
+            Some(_) => self.get_at(index).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The raw value of the column, `None` if the value is NULL.
+    pub fn get_raw(&self, column: &str) -> Result<Option<&String>, Error> {
+        get_table_row_value(&self.row, &self.columns, STRUCT_NAME, column)
+    }
+
+    /// The raw value of the column at `index`, `None` if the value is NULL.
+    pub fn get_raw_at(&self, index: usize) -> Result<Option<&String>, Error> {
+        self.cells().get(index)
+            .map(|cell| cell.v.as_ref())
+            .ok_or_else(|| Error::Parse {
+                struct_name: STRUCT_NAME.to_string(),
+                field: index.to_string(),
+                value: None,
+                message: "the row has no value for the column".to_string(),
+            })
+    }
+
+    /// The number of values of the row.
+    pub fn len(&self) -> usize {
+        self.cells().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells().is_empty()
+    }
+
+    pub fn table_row(&self) -> &TableRow {
+        &self.row
+    }
+
+    fn cells(&self) -> &[google_bigquery2::api::TableCell] {
+        self.row.f.as_deref().unwrap_or_default()
+    }
+}
+
+impl Index<usize> for QueryResultRow {
+    type Output = Option<String>;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.cells()[index].v
+    }
+}
+
+impl Index<&str> for QueryResultRow {
+    type Output = Option<String>;
+
+    fn index(&self, column: &str) -> &Self::Output {
+        match self.columns.get(column) {
+            Some(index) => &self[*index],
+            None => panic!("The query result has no column {}", column),
+        }
+    }
+}
+
+fn parse<T: ConvertValueToBigqueryParamValue>(column: &str, value: Option<&String>) -> Result<T, Error> {
+    let error = |value: Option<&String>, message: String| Error::Parse {
+        struct_name: STRUCT_NAME.to_string(),
+        field: column.to_string(),
+        value: value.cloned(),
+        message,
+    };
+    match value {
+        Some(v) => T::from_bigquery_value(v).map_err(|e| error(Some(v), e.to_string())),
+        None => Err(error(None, "the value is NULL".to_string())),
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use crate::error::Error;
+
+/// Decides which failed requests are sent again and how long to wait in between.
+///
+/// The delay before attempt `n + 1` is `initial_backoff * multiplier^(n - 1)`, capped at
+/// `max_backoff` and reduced by a random amount of up to `jitter` (0.0 - 1.0) of itself.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// The maximum number of attempts, including the first one. `1` disables retries.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    pub jitter: f64,
+    /// BigQuery error reasons (see [`Error::Http`]) that are retried.
+    pub retryable_reasons: Vec<String>,
+    /// HTTP status codes that are retried.
+    pub retryable_status_codes: Vec<u16>,
+    /// Parts of error messages that are retried, for errors that don't have a specific reason.
+    pub retryable_messages: Vec<String>,
+    /// Whether connection errors and request timeouts are retried.
+    pub retry_transport_errors: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(32),
+            multiplier: 2.0,
+            jitter: 0.5,
+            retryable_reasons: vec![
+                "rateLimitExceeded".to_string(),
+                "backendError".to_string(),
+                "internalError".to_string(),
+                "jobRateLimitExceeded".to_string(),
+            ],
+            retryable_status_codes: vec![500, 502, 503, 504],
+            retryable_messages: vec![
+                "Could not serialize access to table".to_string(),
+            ],
+            retry_transport_errors: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// The time to wait after the given (1 based) attempt failed.
+    pub fn get_backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        let backoff = backoff.min(self.max_backoff.as_secs_f64());
+        let jitter = self.jitter.clamp(0.0, 1.0) * random_fraction();
+        Duration::from_secs_f64(backoff * (1.0 - jitter))
+    }
+
+    pub(crate) fn is_retryable(&self, error: &Error) -> bool {
+        match error {
+            Error::Request(_) => self.retry_transport_errors && error.is_transport_error(),
+            Error::Http { status, reason, message, .. } => {
+                self.retryable_status_codes.contains(status)
+                    || reason.as_ref().is_some_and(|reason| self.retryable_reasons.contains(reason))
+                    || self.retryable_messages.iter().any(|m| message.contains(m.as_str()))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A random number in `[0, 1)`, good enough to spread out retries.
+fn random_fraction() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
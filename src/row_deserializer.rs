@@ -0,0 +1,321 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use google_bigquery2::api::{TableCell, TableFieldSchema, TableRow, TableSchema};
+use serde::de::{self, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+
+use crate::error::Error;
+
+/// The name used for the struct in the [`Error::Parse`] errors of rows that are not read into a struct.
+const STRUCT_NAME: &str = "TableRow";
+
+/// Reads a `T` from a row of a query result with the schema of the result.
+///
+/// See [`TableRowDeserializer`] for how the columns are mapped to `T`.
+pub fn from_table_row<'de, T: Deserialize<'de>>(row: &'de TableRow, schema: &'de TableSchema) -> Result<T, Error> {
+    T::deserialize(TableRowDeserializer::new(row, schema))
+}
+
+/// A serde [`Deserializer`](de::Deserializer) for a row of a query result and the schema of the result
+/// (the `rows` and the `schema` of a `QueryResponse`).
+///
+/// Structs and maps are read by the names of the columns, sequences and tuples by their order.
+/// Columns without a field in the struct are ignored, NULL values can be read into `Option`s.
+/// `INTEGER`, `FLOAT` and `BOOLEAN` values are read as numbers and booleans, but any value can
+/// be read into a number, a bool or a string if it can be parsed as such. All other values
+/// (e.g. `DATETIME`) are read as the strings BigQuery returns for them.
+pub struct TableRowDeserializer<'de> {
+    row: &'de TableRow,
+    schema: &'de TableSchema,
+}
+
+impl<'de> TableRowDeserializer<'de> {
+    pub fn new(row: &'de TableRow, schema: &'de TableSchema) -> Self {
+        Self { row, schema }
+    }
+
+    fn columns(&self, struct_name: &'static str) -> Result<Columns<'de>, Error> {
+        let fields = self.schema.fields.as_deref().unwrap_or_default();
+        let cells = self.row.f.as_deref().unwrap_or_default();
+        if let Some(field) = fields.get(cells.len()) {
+            return Err(Error::Parse {
+                struct_name: struct_name.to_string(),
+                field: field.name.clone().unwrap_or_default(),
+                value: None,
+                message: "the row has no value for the column".to_string(),
+            });
+        }
+        Ok(Columns {
+            struct_name,
+            columns: fields.iter().zip(cells.iter()),
+            current: None,
+        })
+    }
+}
+
+impl<'de> de::Deserializer<'de> for TableRowDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_map(self.columns(STRUCT_NAME)?).map_err(|e| set_struct_name(e, STRUCT_NAME))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self,
+                                           name: &'static str,
+                                           _fields: &'static [&'static str],
+                                           visitor: V)
+                                           -> Result<V::Value, Error> {
+        visitor.visit_map(self.columns(name)?).map_err(|e| set_struct_name(e, name))
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(self.columns(STRUCT_NAME)?)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, name: &'static str, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(self.columns(name)?)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct enum identifier ignored_any
+    }
+}
+
+/// The columns of a row with their values, read one after the other as map entries or sequence elements.
+struct Columns<'de> {
+    struct_name: &'static str,
+    columns: std::iter::Zip<std::slice::Iter<'de, TableFieldSchema>, std::slice::Iter<'de, TableCell>>,
+    current: Option<(&'de TableFieldSchema, &'de TableCell)>,
+}
+
+impl<'de> Columns<'de> {
+    fn deserialize_value<T: DeserializeSeed<'de>>(&self,
+                                                  seed: T,
+                                                  field: &'de TableFieldSchema,
+                                                  cell: &'de TableCell)
+                                                  -> Result<T::Value, Error> {
+        let value = cell.v.as_deref();
+        seed.deserialize(ValueDeserializer { value, type_: field.type_.as_deref().unwrap_or_default() })
+            .map_err(|e| match e {
+                e @ Error::Parse { .. } => e,
+                e => Error::Parse {
+                    struct_name: self.struct_name.to_string(),
+                    field: field.name.clone().unwrap_or_default(),
+                    value: value.map(|value| value.to_string()),
+                    message: e.to_string(),
+                },
+            })
+    }
+}
+
+impl<'de> MapAccess<'de> for Columns<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Error> {
+        match self.columns.next() {
+            Some((field, cell)) => {
+                self.current = Some((field, cell));
+                let name = field.name.as_deref().unwrap_or_default();
+                seed.deserialize(name.into_deserializer()).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let (field, cell) = self.current.take()
+            .ok_or_else(|| Error::Other("next_value_seed was called before next_key_seed".to_string()))?;
+        self.deserialize_value(seed, field, cell)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.columns.len())
+    }
+}
+
+impl<'de> SeqAccess<'de> for Columns<'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Error> {
+        match self.columns.next() {
+            Some((field, cell)) => self.deserialize_value(seed, field, cell).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.columns.len())
+    }
+}
+
+/// The raw value of a single column (`None` for NULL) with the type of the column.
+struct ValueDeserializer<'de> {
+    value: Option<&'de str>,
+    type_: &'de str,
+}
+
+impl<'de> ValueDeserializer<'de> {
+    fn get_value(&self) -> Result<&'de str, Error> {
+        self.value.ok_or_else(|| Error::Other("the value is NULL".to_string()))
+    }
+
+    fn parse<T: FromStr>(&self) -> Result<T, Error>
+        where T::Err: Display {
+        self.get_value()?.parse().map_err(|e: T::Err| Error::Other(e.to_string()))
+    }
+
+    fn parse_bool(&self) -> Result<bool, Error> {
+        match self.get_value()?.to_uppercase().as_str() {
+            "TRUE" => Ok(true),
+            "FALSE" => Ok(false),
+            _ => Err(Error::Other("Invalid value for bool".to_string())),
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let value = match self.value {
+            Some(value) => value,
+            None => return visitor.visit_none(),
+        };
+        match self.type_ {
+            "INTEGER" | "INT64" => visitor.visit_i64(self.parse()?),
+            "FLOAT" | "FLOAT64" => visitor.visit_f64(self.parse()?),
+            "BOOLEAN" | "BOOL" => visitor.visit_bool(self.parse_bool()?),
+            _ => visitor.visit_borrowed_str(value),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_bool(self.parse_bool()?)
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i8(self.parse()?)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i16(self.parse()?)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i32(self.parse()?)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i64(self.parse()?)
+    }
+
+    fn deserialize_i128<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i128(self.parse()?)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u8(self.parse()?)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u16(self.parse()?)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u32(self.parse()?)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u64(self.parse()?)
+    }
+
+    fn deserialize_u128<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u128(self.parse()?)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_f32(self.parse()?)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_f64(self.parse()?)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_char(self.parse()?)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_borrowed_str(self.get_value()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value {
+            Some(_) => visitor.visit_some(self),
+            None => visitor.visit_none(),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self,
+                                         _name: &'static str,
+                                         _variants: &'static [&'static str],
+                                         visitor: V)
+                                         -> Result<V::Value, Error> {
+        visitor.visit_enum(self.get_value()?.into_deserializer())
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bytes byte_buf unit_struct seq tuple tuple_struct map struct identifier
+    }
+}
+
+/// Fills in the name of the struct of the errors of missing fields, which serde does not pass on.
+fn set_struct_name(error: Error, name: &str) -> Error {
+    match error {
+        Error::Parse { struct_name, field, value, message } if struct_name.is_empty() =>
+            Error::Parse { struct_name: name.to_string(), field, value, message },
+        e => e,
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error::Other(msg.to_string())
+    }
+
+    /// An [`Error::Parse`] without the name of the struct, see [`set_struct_name`].
+    fn missing_field(field: &'static str) -> Self {
+        Error::Parse {
+            struct_name: String::new(),
+            field: field.to_string(),
+            value: None,
+            message: "the query result has no column for the field".to_string(),
+        }
+    }
+}
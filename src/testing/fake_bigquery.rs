@@ -0,0 +1,942 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use google_bigquery2::api::{GetQueryResultsResponse, JobReference, QueryParameterType, QueryParameterValue, QueryRequest, QueryResponse, TableCell,
+                            TableFieldSchema, TableRow, TableSchema};
+
+use crate::error::Error;
+use crate::executor::{GetQueryResultsRequest, QueryExecutor};
+use crate::testing::sql::{self, Expr, Literal, MergeClause, Select, Statement};
+
+/// An in-memory stand-in for BigQuery that answers the statements this crate generates.
+///
+/// Tables are identified by `project.dataset.table` and have to be created with
+/// [`create_table`](FakeBigquery::create_table) before they can be used. Clones share the same tables,
+/// so a clone can be handed to the client while the original is used to set up and check the data.
+///
+/// ```
+/// # use google_bigquery::BigqueryClient;
+/// # use google_bigquery::testing::FakeBigquery;
+/// # async fn example() {
+/// let fake = FakeBigquery::new();
+/// fake.create_table("my-project.my_dataset.Infos", &[("Id", "INT64"), ("info1", "STRING")]);
+/// fake.insert_row("my-project.my_dataset.Infos", &[("Id", Some("1")), ("info1", Some("a"))]);
+/// let client = BigqueryClient::builder()
+///     .project_id("my-project")
+///     .dataset_id("my_dataset")
+///     .executor(fake.clone())
+///     .build().await.unwrap();
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct FakeBigquery {
+    state: Arc<Mutex<FakeState>>,
+}
+
+#[derive(Default)]
+struct FakeState {
+    tables: HashMap<String, FakeTable>,
+    requests: Vec<QueryRequest>,
+    results_requests: Vec<GetQueryResultsRequest>,
+    job_count: usize,
+    jobs: HashMap<String, FakeJob>,
+    page_size: Option<usize>,
+    incomplete_responses: usize,
+    lost_responses: usize,
+    concurrent_update_errors: usize,
+    /// The results (responses or errors) of the requests with a `request_id`, to answer duplicates of them.
+    results_by_request_id: HashMap<String, Result<QueryResponse, String>>,
+}
+
+/// The complete result of a query, handed out page by page.
+struct FakeJob {
+    response: QueryResponse,
+    incomplete_responses: usize,
+}
+
+struct FakeTable {
+    columns: Vec<FakeColumn>,
+    rows: Vec<Vec<Value>>,
+}
+
+struct FakeColumn {
+    name: String,
+    type_: String,
+}
+
+/// The columns and rows a select returned.
+struct SelectResult {
+    fields: Vec<FakeColumn>,
+    rows: Vec<Vec<Value>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    /// The microseconds since the epoch.
+    Timestamp(i64),
+    Array(Vec<Value>),
+}
+
+impl FakeBigquery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates (or replaces) an empty table with the given `(name, type)` columns.
+    pub fn create_table(&self, table: &str, columns: &[(&str, &str)]) {
+        let columns = columns.iter()
+            .map(|(name, type_)| FakeColumn { name: name.to_string(), type_: normalize_type(type_) })
+            .collect();
+        self.state.lock().unwrap().tables.insert(table.to_string(), FakeTable { columns, rows: vec![] });
+    }
+
+    /// Adds a row to the table. Values are given as BigQuery returns them, missing columns are NULL.
+    ///
+    /// Panics if the table or a column does not exist or a value does not fit its column.
+    pub fn insert_row(&self, table: &str, values: &[(&str, Option<&str>)]) {
+        let mut state = self.state.lock().unwrap();
+        let table = state.tables.get_mut(table)
+            .unwrap_or_else(|| panic!("Table {} does not exist in the fake", table));
+        let mut row = vec![Value::Null; table.columns.len()];
+        for (name, value) in values {
+            let index = table.get_column_index(name)
+                .unwrap_or_else(|| panic!("Column {} does not exist in the fake", name));
+            let value = match value {
+                Some(v) => Value::String(v.to_string()),
+                None => Value::Null,
+            };
+            row[index] = coerce(value, &table.columns[index].type_)
+                .unwrap_or_else(|e| panic!("Invalid value for column {}: {}", name, e));
+        }
+        table.rows.push(row);
+    }
+
+    /// All rows of the table as column name to value (as BigQuery would return it).
+    pub fn get_rows(&self, table: &str) -> Vec<HashMap<String, Option<String>>> {
+        let state = self.state.lock().unwrap();
+        let table = state.tables.get(table)
+            .unwrap_or_else(|| panic!("Table {} does not exist in the fake", table));
+        table.rows.iter()
+            .map(|row| table.columns.iter()
+                .zip(row.iter())
+                .map(|(column, value)| (column.name.clone(), value.to_bigquery_string()))
+                .collect())
+            .collect()
+    }
+
+    /// Every request the fake received so far, in order.
+    pub fn get_requests(&self) -> Vec<QueryRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Every `jobs.getQueryResults` request the fake received so far, in order.
+    pub fn get_results_requests(&self) -> Vec<GetQueryResultsRequest> {
+        self.state.lock().unwrap().results_requests.clone()
+    }
+
+    /// Splits the rows of the following queries into pages of `page_size` rows,
+    /// which have to be fetched with `jobs.getQueryResults`.
+    pub fn set_page_size(&self, page_size: Option<usize>) {
+        self.state.lock().unwrap().page_size = page_size;
+    }
+
+    /// Lets the jobs of the following queries report that they are not complete yet
+    /// for the first `incomplete_responses` responses (including the one to the query itself).
+    pub fn set_incomplete_responses(&self, incomplete_responses: usize) {
+        self.state.lock().unwrap().incomplete_responses = incomplete_responses;
+    }
+
+    /// Runs the following `lost_responses` queries, but fails them with a timeout
+    /// as if their responses were lost on the way back.
+    pub fn set_lost_responses(&self, lost_responses: usize) {
+        self.state.lock().unwrap().lost_responses = lost_responses;
+    }
+
+    /// Fails the following `concurrent_update_errors` DML statements with the error BigQuery returns
+    /// when another statement changed the table at the same time.
+    pub fn set_concurrent_update_errors(&self, concurrent_update_errors: usize) {
+        self.state.lock().unwrap().concurrent_update_errors = concurrent_update_errors;
+    }
+
+    /// Whether the response to the query that just ran is lost.
+    fn take_lost_response(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let lost = state.lost_responses > 0;
+        state.lost_responses = state.lost_responses.saturating_sub(1);
+        lost
+    }
+
+    fn run(&self, req: &QueryRequest, project_id: &str) -> Result<QueryResponse, String> {
+        let mut state = self.state.lock().unwrap();
+        state.requests.push(req.clone());
+        // like BigQuery, a request with a known request id is answered with the result of its job
+        // (even if that job failed) without running it again
+        if let Some(result) = req.request_id.as_ref().and_then(|id| state.results_by_request_id.get(id)) {
+            return result.clone();
+        }
+        let result = state.run(req, project_id);
+        if let Some(request_id) = &req.request_id {
+            state.results_by_request_id.insert(request_id.clone(), result.clone());
+        }
+        result
+    }
+
+    fn get_results(&self, req: &GetQueryResultsRequest) -> Result<GetQueryResultsResponse, String> {
+        let mut state = self.state.lock().unwrap();
+        state.results_requests.push(req.clone());
+        let page_size = state.page_size;
+        let job = state.jobs.get_mut(&req.job_id)
+            .ok_or_else(|| format!("Not found: Job {}", req.job_id))?;
+        job.next_page(req.page_token.as_deref(), page_size)
+    }
+}
+
+impl FakeState {
+    /// Runs the request as a new job.
+    fn run(&mut self, req: &QueryRequest, project_id: &str) -> Result<QueryResponse, String> {
+        self.job_count += 1;
+        let job_reference = JobReference {
+            job_id: Some(format!("fake_job_{}", self.job_count)),
+            location: req.location.clone(),
+            project_id: Some(project_id.to_string()),
+        };
+
+        let query = req.query.as_deref().ok_or("No query given")?;
+        let params = get_params(req)?;
+        let default_dataset = req.default_dataset.as_ref().and_then(|d| d.dataset_id.clone());
+        let resolve = |table: &str| resolve_table(table, project_id, default_dataset.as_deref());
+
+        let statement = sql::parse(query)?;
+        if let Statement::Insert { table, .. } | Statement::Update { table, .. } | Statement::Delete { table, .. }
+        | Statement::Merge { table, .. } = &statement {
+            if self.concurrent_update_errors > 0 {
+                self.concurrent_update_errors -= 1;
+                return Err(format!("Could not serialize access to table {} due to concurrent update", resolve(table)));
+            }
+        }
+        let mut response = match statement {
+            Statement::Select(select) => self.select(&select, &params, &resolve)?.into_response(),
+            Statement::Insert { table, columns, rows } => {
+                let table_id = resolve(&table);
+                let table = self.tables.get_mut(&table_id).ok_or_else(|| not_found(&table_id))?;
+                dml_response(table.insert(&columns, &rows, &params)?)
+            }
+            Statement::Update { table, assignments, filter } => {
+                let table_id = resolve(&table);
+                let table = self.tables.get_mut(&table_id).ok_or_else(|| not_found(&table_id))?;
+                dml_response(table.update(&assignments, filter.as_ref(), &params)?)
+            }
+            Statement::Delete { table, filter } => {
+                let table_id = resolve(&table);
+                let table = self.tables.get_mut(&table_id).ok_or_else(|| not_found(&table_id))?;
+                dml_response(table.delete(&filter, &params)?)
+            }
+            Statement::Merge { table, alias, source, source_alias, on, clauses } => {
+                let mut selects = source.iter();
+                let mut source = self.select(selects.next().ok_or("MERGE without source")?, &params, &resolve)?;
+                for select in selects {
+                    let result = self.select(select, &params, &resolve)?;
+                    if result.fields.len() != source.fields.len() {
+                        return Err("Queries in UNION ALL have mismatched column count".to_string());
+                    }
+                    source.rows.extend(result.rows);
+                }
+                let table_id = resolve(&table);
+                let table = self.tables.get_mut(&table_id).ok_or_else(|| not_found(&table_id))?;
+                let alias = alias.unwrap_or_else(|| table_id.rsplit('.').next().unwrap_or_default().to_string());
+                dml_response(table.merge(&alias, &source, source_alias.as_deref(), &on, &clauses, &params)?)
+            }
+        };
+        response.job_reference = Some(job_reference.clone());
+        response.job_complete = Some(true);
+
+        let job_id = job_reference.job_id.clone().unwrap_or_default();
+        let mut job = FakeJob { response, incomplete_responses: self.incomplete_responses };
+        let page = job.next_page(None, self.page_size);
+        self.jobs.insert(job_id, job);
+        let page = page?;
+        let response = QueryResponse {
+            job_reference: Some(job_reference),
+            job_complete: page.job_complete,
+            page_token: page.page_token,
+            rows: page.rows,
+            schema: page.schema,
+            total_rows: page.total_rows,
+            num_dml_affected_rows: page.num_dml_affected_rows,
+            ..Default::default()
+        };
+        Ok(response)
+    }
+
+    /// Runs the select on its table, or on a single empty row if it has no `FROM`.
+    fn select(&self, select: &Select, params: &HashMap<String, Value>, resolve: &dyn Fn(&str) -> String) -> Result<SelectResult, String> {
+        match &select.table {
+            Some(table) => {
+                let table_id = resolve(table);
+                let table = self.tables.get(&table_id).ok_or_else(|| not_found(&table_id))?;
+                table.select(select, params)
+            }
+            None => FakeTable { columns: vec![], rows: vec![vec![]] }.select(select, params),
+        }
+    }
+}
+
+impl FakeJob {
+    /// The page starting at the row the page token points to (the page tokens are just row offsets).
+    fn next_page(&mut self, page_token: Option<&str>, page_size: Option<usize>) -> Result<GetQueryResultsResponse, String> {
+        if self.incomplete_responses > 0 {
+            self.incomplete_responses -= 1;
+            return Ok(GetQueryResultsResponse {
+                job_reference: self.response.job_reference.clone(),
+                job_complete: Some(false),
+                ..Default::default()
+            });
+        }
+        let start: usize = match page_token {
+            Some(token) => token.parse().map_err(|_| format!("Invalid page token {}", token))?,
+            None => 0,
+        };
+        let all_rows = self.response.rows.clone().unwrap_or_default();
+        let end = page_size.map_or(all_rows.len(), |size| (start + size).min(all_rows.len()));
+        let rows: Vec<TableRow> = all_rows.get(start..end).map(|rows| rows.to_vec()).unwrap_or_default();
+        Ok(GetQueryResultsResponse {
+            job_reference: self.response.job_reference.clone(),
+            job_complete: Some(true),
+            page_token: Some(end.to_string()).filter(|_| end < all_rows.len()),
+            rows: Some(rows).filter(|rows| !rows.is_empty()),
+            schema: self.response.schema.clone(),
+            total_rows: self.response.total_rows.clone(),
+            num_dml_affected_rows: self.response.num_dml_affected_rows.clone(),
+            ..Default::default()
+        })
+    }
+}
+
+#[async_trait]
+impl QueryExecutor for FakeBigquery {
+    async fn query(&self, req: QueryRequest, project_id: &str) -> Result<QueryResponse, Error> {
+        let response = self.run(&req, project_id).map_err(to_error)?;
+        if self.take_lost_response() {
+            return Err(Error::Request(Box::new(google_bigquery2::Error::Io(
+                std::io::Error::new(std::io::ErrorKind::TimedOut, "The response was lost")))));
+        }
+        Ok(response)
+    }
+
+    async fn get_query_results(&self, req: GetQueryResultsRequest, _project_id: &str) -> Result<GetQueryResultsResponse, Error> {
+        self.get_results(&req).map_err(to_error)
+    }
+}
+
+fn to_error(message: String) -> Error {
+    let (status, reason) = match message.starts_with("Not found") {
+        true => (404, "notFound"),
+        false => (400, "invalidQuery"),
+    };
+    Error::Http {
+        status,
+        reason: Some(reason.to_string()),
+        message,
+        location: None,
+    }
+}
+
+fn not_found(table: &str) -> String {
+    format!("Not found: Table {} was not found", table)
+}
+
+fn dml_response(affected_rows: usize) -> QueryResponse {
+    QueryResponse {
+        num_dml_affected_rows: Some(affected_rows.to_string()),
+        ..Default::default()
+    }
+}
+
+fn resolve_table(table: &str, project_id: &str, default_dataset: Option<&str>) -> String {
+    match table.split('.').count() {
+        1 => format!("{}.{}.{}", project_id, default_dataset.unwrap_or_default(), table),
+        2 => format!("{}.{}", project_id, table),
+        _ => table.to_string(),
+    }
+}
+
+fn normalize_type(type_: &str) -> String {
+    match type_.to_uppercase().as_str() {
+        "INTEGER" => "INT64".to_string(),
+        "BOOLEAN" => "BOOL".to_string(),
+        "FLOAT" => "FLOAT64".to_string(),
+        t => t.to_string(),
+    }
+}
+
+fn get_params(req: &QueryRequest) -> Result<HashMap<String, Value>, String> {
+    let mut params = HashMap::new();
+    for param in req.query_parameters.iter().flatten() {
+        let name = param.name.clone().ok_or("Query parameters must have a name")?;
+        let value = match (&param.parameter_type, &param.parameter_value) {
+            (Some(type_), Some(value)) => param_to_value(type_, value)?,
+            (Some(_), None) => Value::Null,
+            (None, _) => return Err(format!("Query parameter {} has no type", name)),
+        };
+        params.insert(name.to_lowercase(), value);
+    }
+    Ok(params)
+}
+
+fn param_to_value(type_: &QueryParameterType, value: &QueryParameterValue) -> Result<Value, String> {
+    let type_name = type_.type_.as_deref().map(normalize_type).unwrap_or_default();
+    if type_name == "ARRAY" {
+        let element_type = type_.array_type.clone().flatten().ok_or("Array parameter without array type")?;
+        let values = value.array_values.iter()
+            .flatten()
+            .map(|v| param_to_value(&element_type, v))
+            .collect::<Result<Vec<Value>, String>>()?;
+        return Ok(Value::Array(values));
+    }
+    match &value.value {
+        None => Ok(Value::Null),
+        Some(v) => coerce(Value::String(v.clone()), &type_name),
+    }
+}
+
+/// Converts the value to the representation of the given column type.
+fn coerce(value: Value, type_: &str) -> Result<Value, String> {
+    let text = match &value {
+        Value::Null => return Ok(Value::Null),
+        Value::String(s) => s.clone(),
+        Value::Array(_) => return Ok(value),
+        v => v.to_bigquery_string().unwrap_or_default(),
+    };
+    let invalid = || format!("Could not cast {:?} to {}", text, type_);
+    match type_ {
+        "INT64" => text.parse().map(Value::Int).map_err(|_| invalid()),
+        "FLOAT64" | "NUMERIC" | "BIGNUMERIC" => text.parse().map(Value::Float).map_err(|_| invalid()),
+        "BOOL" => match text.to_lowercase().as_str() {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            _ => Err(invalid()),
+        },
+        "TIMESTAMP" => parse_timestamp(&text).map(Value::Timestamp).ok_or_else(invalid),
+        _ => Ok(Value::String(text)),
+    }
+}
+
+/// The microseconds of a TIMESTAMP given as seconds since the epoch (as BigQuery returns them)
+/// or as `YYYY-MM-DD HH:MM:SS[.ffffff]` with an optional UTC suffix (as it is written in queries).
+fn parse_timestamp(text: &str) -> Option<i64> {
+    if let Ok(seconds) = text.parse::<f64>() {
+        return Some((seconds * 1_000_000.0).round() as i64);
+    }
+    let text = text.trim_end_matches(" UTC").trim_end_matches("+00:00").trim_end_matches('Z').replace('T', " ");
+    chrono::NaiveDateTime::parse_from_str(&text, "%Y-%m-%d %H:%M:%S%.f").ok()
+        .map(|time| time.and_utc().timestamp_micros())
+}
+
+impl Value {
+    fn to_bigquery_string(&self) -> Option<String> {
+        match self {
+            Value::Null => None,
+            Value::Bool(b) => Some(b.to_string()),
+            Value::Int(i) => Some(i.to_string()),
+            Value::Float(f) => Some(f.to_string()),
+            Value::String(s) => Some(s.clone()),
+            // like BigQuery, the seconds since the epoch in scientific notation, e.g. 1.6961728001234E9
+            Value::Timestamp(micros) => Some(format!("{:E}", *micros as f64 / 1_000_000.0)),
+            Value::Array(values) => Some(format!("[{}]", values.iter()
+                .map(|v| v.to_bigquery_string().unwrap_or_else(|| "NULL".to_string()))
+                .collect::<Vec<String>>()
+                .join(", "))),
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null | Value::String(_) => "STRING",
+            Value::Bool(_) => "BOOL",
+            Value::Int(_) => "INT64",
+            Value::Float(_) => "FLOAT64",
+            Value::Timestamp(_) => "TIMESTAMP",
+            Value::Array(_) => "ARRAY",
+        }
+    }
+
+    fn is_true(&self) -> bool {
+        matches!(self, Value::Bool(true))
+    }
+}
+
+/// Compares two values, converting strings to the type of the other side. `None` if one of them is NULL.
+fn compare(a: &Value, b: &Value) -> Result<Option<Ordering>, String> {
+    let ordering = match (a, b) {
+        (Value::Null, _) | (_, Value::Null) => return Ok(None),
+        (Value::Int(a), Value::Int(b)) => a.cmp(b),
+        (Value::Float(a), Value::Float(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+        (Value::Int(a), Value::Float(b)) => (*a as f64).partial_cmp(b).unwrap_or(Ordering::Equal),
+        (Value::Float(a), Value::Int(b)) => a.partial_cmp(&(*b as f64)).unwrap_or(Ordering::Equal),
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Timestamp(a), Value::Timestamp(b)) => a.cmp(b),
+        (Value::String(_), other) => return compare(&coerce(a.clone(), other.type_name())?, b),
+        (other, Value::String(_)) => return compare(a, &coerce(b.clone(), other.type_name())?),
+        (a, b) => return Err(format!("No matching signature for comparing {:?} and {:?}", a, b)),
+    };
+    Ok(Some(ordering))
+}
+
+/// `%` matches any number of characters, `_` exactly one.
+fn like(text: &[char], pattern: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('%') => (0..=text.len()).any(|i| like(&text[i..], &pattern[1..])),
+        Some('_') => !text.is_empty() && like(&text[1..], &pattern[1..]),
+        Some(c) => text.first() == Some(c) && like(&text[1..], &pattern[1..]),
+    }
+}
+
+fn is_aggregate(expr: &Expr) -> bool {
+    matches!(expr, Expr::Function { name, .. } if ["count", "sum", "min", "max"].contains(&name.as_str()))
+}
+
+struct Evaluator<'e> {
+    columns: &'e [FakeColumn],
+    params: &'e HashMap<String, Value>,
+}
+
+impl<'e> Evaluator<'e> {
+    fn eval(&self, expr: &Expr, row: Option<&[Value]>) -> Result<Value, String> {
+        let value = match expr {
+            Expr::Literal(literal) => match literal {
+                Literal::Null => Value::Null,
+                Literal::Bool(b) => Value::Bool(*b),
+                Literal::Int(i) => Value::Int(*i),
+                Literal::Float(f) => Value::Float(*f),
+                Literal::String(s) => Value::String(s.clone()),
+            },
+            Expr::Param(name) => self.params.get(&name.to_lowercase())
+                .cloned()
+                .ok_or_else(|| format!("Query parameter '{}' not found", name))?,
+            Expr::Column(name) => {
+                let row = row.ok_or_else(|| format!("Unrecognized name: {}", name))?;
+                let index = self.get_column_index(name)?;
+                row[index].clone()
+            }
+            Expr::Star => return Err("Unexpected *".to_string()),
+            Expr::Function { name, .. } => return Err(format!("Function not found: {}", name)),
+            Expr::Binary { op, left, right } => {
+                let left = self.eval(left, row)?;
+                let right = self.eval(right, row)?;
+                match op.as_str() {
+                    "and" => match (&left, &right) {
+                        (Value::Bool(false), _) | (_, Value::Bool(false)) => Value::Bool(false),
+                        (Value::Bool(true), Value::Bool(true)) => Value::Bool(true),
+                        _ => Value::Null,
+                    },
+                    "or" => match (&left, &right) {
+                        (Value::Bool(true), _) | (_, Value::Bool(true)) => Value::Bool(true),
+                        (Value::Bool(false), Value::Bool(false)) => Value::Bool(false),
+                        _ => Value::Null,
+                    },
+                    "+" | "-" => match (&left, &right) {
+                        (Value::Null, _) | (_, Value::Null) => Value::Null,
+                        (Value::Int(a), Value::Int(b)) => Value::Int(if op == "+" { a + b } else { a - b }),
+                        _ => return Err(format!("No matching signature for operator {}", op)),
+                    },
+                    _ => match compare(&left, &right)? {
+                        None => Value::Null,
+                        Some(ordering) => Value::Bool(match op.as_str() {
+                            "=" => ordering == Ordering::Equal,
+                            "!=" => ordering != Ordering::Equal,
+                            "<" => ordering == Ordering::Less,
+                            "<=" => ordering != Ordering::Greater,
+                            ">" => ordering == Ordering::Greater,
+                            ">=" => ordering != Ordering::Less,
+                            _ => return Err(format!("Unknown operator {}", op)),
+                        }),
+                    },
+                }
+            }
+            Expr::Not(expr) => match self.eval(expr, row)? {
+                Value::Bool(b) => Value::Bool(!b),
+                _ => Value::Null,
+            },
+            Expr::Negate(expr) => match self.eval(expr, row)? {
+                Value::Int(i) => Value::Int(-i),
+                Value::Float(f) => Value::Float(-f),
+                Value::Null => Value::Null,
+                v => return Err(format!("Can not negate {:?}", v)),
+            },
+            Expr::IsNull { expr, negated } => Value::Bool((self.eval(expr, row)? == Value::Null) != *negated),
+            Expr::InList { expr, list, negated } => {
+                let values = list.iter()
+                    .map(|e| self.eval(e, row))
+                    .collect::<Result<Vec<Value>, String>>()?;
+                self.eval_in(self.eval(expr, row)?, &values, *negated)?
+            }
+            Expr::InUnnest { expr, array, negated } => match self.eval(array, row)? {
+                Value::Array(values) => self.eval_in(self.eval(expr, row)?, &values, *negated)?,
+                Value::Null => self.eval_in(self.eval(expr, row)?, &[], *negated)?,
+                v => return Err(format!("Values referenced in UNNEST must be arrays, got {:?}", v)),
+            },
+            Expr::Between { expr, low, high, negated } => {
+                let value = self.eval(expr, row)?;
+                let low = compare(&value, &self.eval(low, row)?)?;
+                let high = compare(&value, &self.eval(high, row)?)?;
+                match (low, high) {
+                    (Some(low), Some(high)) => Value::Bool((low != Ordering::Less && high != Ordering::Greater) != *negated),
+                    _ => Value::Null,
+                }
+            }
+            Expr::Like { expr, pattern, negated } => match (self.eval(expr, row)?, self.eval(pattern, row)?) {
+                (Value::String(text), Value::String(pattern)) => {
+                    let text: Vec<char> = text.chars().collect();
+                    let pattern: Vec<char> = pattern.chars().collect();
+                    Value::Bool(like(&text, &pattern) != *negated)
+                }
+                _ => Value::Null,
+            },
+        };
+        Ok(value)
+    }
+
+    fn eval_in(&self, value: Value, values: &[Value], negated: bool) -> Result<Value, String> {
+        if value == Value::Null {
+            return Ok(Value::Null);
+        }
+        for v in values {
+            if compare(&value, v)? == Some(Ordering::Equal) {
+                return Ok(Value::Bool(!negated));
+            }
+        }
+        Ok(Value::Bool(negated))
+    }
+
+    fn eval_aggregate(&self, expr: &Expr, rows: &[&Vec<Value>]) -> Result<Value, String> {
+        let (name, distinct, args) = match expr {
+            Expr::Function { name, distinct, args } => (name, *distinct, args),
+            _ => return Err("Expected an aggregate function".to_string()),
+        };
+        let mut values = vec![];
+        for row in rows {
+            let value = match args.first() {
+                Some(Expr::Star) => Value::Bool(true),
+                Some(arg) => self.eval(arg, Some(row))?,
+                None => return Err(format!("{} needs an argument", name)),
+            };
+            if value != Value::Null && !(distinct && values.contains(&value)) {
+                values.push(value);
+            }
+        }
+        let mut extreme = |wanted: Ordering| -> Result<Value, String> {
+            let mut result = Value::Null;
+            for value in values.iter() {
+                if result == Value::Null || compare(value, &result)? == Some(wanted) {
+                    result = value.clone();
+                }
+            }
+            Ok(result)
+        };
+        match name.as_str() {
+            "count" => Ok(Value::Int(values.len() as i64)),
+            "min" => extreme(Ordering::Less),
+            "max" => extreme(Ordering::Greater),
+            "sum" => {
+                let mut sum = Value::Null;
+                for value in values.iter() {
+                    sum = match (sum, value.clone()) {
+                        (Value::Null, v) => v,
+                        (Value::Int(a), Value::Int(b)) => Value::Int(a + b),
+                        (Value::Float(a), Value::Float(b)) => Value::Float(a + b),
+                        (Value::Int(a), Value::Float(b)) | (Value::Float(b), Value::Int(a)) => Value::Float(a as f64 + b),
+                        (_, v) => return Err(format!("No matching signature for SUM({:?})", v)),
+                    };
+                }
+                Ok(sum)
+            }
+            _ => Err(format!("Function not found: {}", name)),
+        }
+    }
+
+    /// Qualified names (`alias.column`) match the column with the same qualified name first
+    /// and otherwise the first column with the same unqualified name.
+    fn get_column_index(&self, name: &str) -> Result<usize, String> {
+        let unqualified = |name: &str| name.rsplit('.').next().unwrap_or(name).to_string();
+        self.columns.iter()
+            .position(|c| c.name.eq_ignore_ascii_case(name))
+            .or_else(|| self.columns.iter().position(|c| unqualified(&c.name).eq_ignore_ascii_case(&unqualified(name))))
+            .ok_or_else(|| format!("Unrecognized name: {}", unqualified(name)))
+    }
+}
+
+impl SelectResult {
+    fn into_response(self) -> QueryResponse {
+        QueryResponse {
+            total_rows: Some(self.rows.len().to_string()),
+            schema: Some(TableSchema {
+                fields: Some(self.fields.into_iter()
+                    .map(|column| TableFieldSchema {
+                        name: Some(column.name),
+                        type_: Some(column.type_),
+                        mode: Some("NULLABLE".to_string()),
+                        ..Default::default()
+                    })
+                    .collect()),
+            }),
+            // like BigQuery, leave the rows out completely if there are none
+            rows: Some(self.rows.into_iter()
+                .map(|row| TableRow {
+                    f: Some(row.into_iter()
+                        .map(|v| TableCell { v: v.to_bigquery_string() })
+                        .collect()),
+                })
+                .collect::<Vec<TableRow>>())
+                .filter(|rows| !rows.is_empty()),
+            ..Default::default()
+        }
+    }
+}
+
+impl FakeTable {
+    fn get_column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c.name.eq_ignore_ascii_case(name))
+    }
+
+    fn evaluator<'e>(&'e self, params: &'e HashMap<String, Value>) -> Evaluator<'e> {
+        Evaluator { columns: &self.columns, params }
+    }
+
+    fn matching_rows(&self, evaluator: &Evaluator, filter: Option<&Expr>) -> Result<Vec<usize>, String> {
+        let mut result = vec![];
+        for (i, row) in self.rows.iter().enumerate() {
+            let matches = match filter {
+                Some(filter) => evaluator.eval(filter, Some(row))?.is_true(),
+                None => true,
+            };
+            if matches {
+                result.push(i);
+            }
+        }
+        Ok(result)
+    }
+
+    fn select(&self, select: &Select, params: &HashMap<String, Value>) -> Result<SelectResult, String> {
+        let evaluator = self.evaluator(params);
+        let mut rows: Vec<&Vec<Value>> = self.matching_rows(&evaluator, select.filter.as_ref())?
+            .into_iter()
+            .map(|i| &self.rows[i])
+            .collect();
+
+        let mut sort_error = None;
+        rows.sort_by(|a, b| {
+            for (expr, ascending) in select.order_by.iter() {
+                let ordering = evaluator.eval(expr, Some(a))
+                    .and_then(|a| evaluator.eval(expr, Some(b)).map(|b| (a, b)))
+                    .and_then(|(a, b)| match (&a, &b) {
+                        (Value::Null, Value::Null) => Ok(Ordering::Equal),
+                        (Value::Null, _) => Ok(Ordering::Less),
+                        (_, Value::Null) => Ok(Ordering::Greater),
+                        _ => compare(&a, &b).map(|o| o.unwrap_or(Ordering::Equal)),
+                    });
+                match ordering {
+                    Ok(Ordering::Equal) => continue,
+                    Ok(ordering) if *ascending => return ordering,
+                    Ok(ordering) => return ordering.reverse(),
+                    Err(e) => {
+                        sort_error = Some(e);
+                        return Ordering::Equal;
+                    }
+                }
+            }
+            Ordering::Equal
+        });
+        if let Some(e) = sort_error {
+            return Err(e);
+        }
+
+        let mut fields = vec![];
+        for (i, item) in select.items.iter().enumerate() {
+            match &item.expr {
+                Expr::Star => fields.extend(self.columns.iter().map(|c| (c.name.clone(), c.type_.clone()))),
+                Expr::Column(name) => {
+                    let column = &self.columns[evaluator.get_column_index(name)?];
+                    fields.push((item.alias.clone().unwrap_or_else(|| column.name.clone()), column.type_.clone()));
+                }
+                expr => {
+                    let type_ = match is_aggregate(expr) {
+                        true => "INT64".to_string(),
+                        false => "STRING".to_string(),
+                    };
+                    fields.push((item.alias.clone().unwrap_or_else(|| format!("f{}_", i)), type_));
+                }
+            }
+        }
+
+        let mut output: Vec<Vec<Value>> = vec![];
+        if select.items.iter().any(|item| is_aggregate(&item.expr)) {
+            let mut row = vec![];
+            for item in select.items.iter() {
+                match is_aggregate(&item.expr) {
+                    true => row.push(evaluator.eval_aggregate(&item.expr, &rows)?),
+                    false => row.push(evaluator.eval(&item.expr, rows.first().map(|r| r.as_slice()))?),
+                }
+            }
+            output.push(row);
+        } else {
+            for source in rows {
+                let mut row = vec![];
+                for item in select.items.iter() {
+                    match &item.expr {
+                        Expr::Star => row.extend(source.iter().cloned()),
+                        expr => row.push(evaluator.eval(expr, Some(source))?),
+                    }
+                }
+                if !(select.distinct && output.contains(&row)) {
+                    output.push(row);
+                }
+            }
+        }
+        let rows: Vec<Vec<Value>> = output.into_iter()
+            .skip(select.offset.unwrap_or(0))
+            .take(select.limit.unwrap_or(usize::MAX))
+            .collect();
+        let fields = fields.into_iter()
+            .map(|(name, type_)| FakeColumn { name, type_ })
+            .collect();
+        Ok(SelectResult { fields, rows })
+    }
+
+    fn insert(&mut self, columns: &[String], rows: &[Vec<Expr>], params: &HashMap<String, Value>) -> Result<usize, String> {
+        let mut indices = vec![];
+        for column in columns {
+            indices.push(self.get_column_index(column).ok_or_else(|| format!("Column {} is not present in table", column))?);
+        }
+        let mut new_rows = vec![];
+        {
+            let evaluator = self.evaluator(params);
+            for values in rows {
+                let mut row = vec![Value::Null; self.columns.len()];
+                for (index, expr) in indices.iter().zip(values.iter()) {
+                    row[*index] = coerce(evaluator.eval(expr, None)?, &self.columns[*index].type_)?;
+                }
+                new_rows.push(row);
+            }
+        }
+        let count = new_rows.len();
+        self.rows.extend(new_rows);
+        Ok(count)
+    }
+
+    /// Updates the rows matched by a source row and inserts the source rows that match nothing,
+    /// depending on the clauses. Fails if a target row is matched by more than one source row.
+    fn merge(&mut self, alias: &str, source: &SelectResult, source_alias: Option<&str>, on: &Expr, clauses: &[MergeClause],
+             params: &HashMap<String, Value>) -> Result<usize, String> {
+        let qualify = |alias: Option<&str>, column: &FakeColumn| FakeColumn {
+            name: match alias {
+                Some(alias) => format!("{}.{}", alias, column.name),
+                None => column.name.clone(),
+            },
+            type_: column.type_.clone(),
+        };
+        let columns: Vec<FakeColumn> = self.columns.iter().map(|c| qualify(Some(alias), c))
+            .chain(source.fields.iter().map(|c| qualify(source_alias, c)))
+            .collect();
+        let evaluator = Evaluator { columns: &columns, params };
+        // the first clause of the kind whose condition holds for the combined row
+        let find_clause = |matched: bool, combined: &[Value]| -> Result<Option<&MergeClause>, String> {
+            for clause in clauses {
+                let condition = match (clause, matched) {
+                    (MergeClause::Update { condition, .. }, true) | (MergeClause::Insert { condition, .. }, false) => condition,
+                    _ => continue,
+                };
+                let applies = match condition {
+                    Some(condition) => evaluator.eval(condition, Some(combined))?.is_true(),
+                    None => true,
+                };
+                if applies {
+                    return Ok(Some(clause));
+                }
+            }
+            Ok(None)
+        };
+
+        let mut updates: Vec<(usize, Vec<Value>)> = vec![];
+        let mut inserts = vec![];
+        for source_row in source.rows.iter() {
+            let mut matched_any = false;
+            for (i, row) in self.rows.iter().enumerate() {
+                let combined: Vec<Value> = row.iter().chain(source_row.iter()).cloned().collect();
+                if !evaluator.eval(on, Some(&combined))?.is_true() {
+                    continue;
+                }
+                matched_any = true;
+                if let Some(MergeClause::Update { assignments, .. }) = find_clause(true, &combined)? {
+                    if updates.iter().any(|(updated, _)| *updated == i) {
+                        return Err("UPDATE/MERGE must match at most one source row for each target row".to_string());
+                    }
+                    let mut row = row.clone();
+                    for (column, expr) in assignments {
+                        let column = column.rsplit('.').next().unwrap_or(column);
+                        let index = self.get_column_index(column).ok_or_else(|| format!("Unrecognized name: {}", column))?;
+                        row[index] = coerce(evaluator.eval(expr, Some(&combined))?, &self.columns[index].type_)?;
+                    }
+                    updates.push((i, row));
+                }
+            }
+            if matched_any {
+                continue;
+            }
+            let combined: Vec<Value> = vec![Value::Null; self.columns.len()].into_iter().chain(source_row.iter().cloned()).collect();
+            if let Some(MergeClause::Insert { columns, values, .. }) = find_clause(false, &combined)? {
+                let mut row = vec![Value::Null; self.columns.len()];
+                for (column, expr) in columns.iter().zip(values.iter()) {
+                    let index = self.get_column_index(column).ok_or_else(|| format!("Column {} is not present in table", column))?;
+                    row[index] = coerce(evaluator.eval(expr, Some(&combined))?, &self.columns[index].type_)?;
+                }
+                inserts.push(row);
+            }
+        }
+        let count = updates.len() + inserts.len();
+        for (i, row) in updates {
+            self.rows[i] = row;
+        }
+        self.rows.extend(inserts);
+        Ok(count)
+    }
+
+    fn delete(&mut self, filter: &Expr, params: &HashMap<String, Value>) -> Result<usize, String> {
+        let matching = self.matching_rows(&self.evaluator(params), Some(filter))?;
+        let mut i = 0;
+        self.rows.retain(|_| {
+            i += 1;
+            !matching.contains(&(i - 1))
+        });
+        Ok(matching.len())
+    }
+
+    fn update(&mut self, assignments: &[(String, Expr)], filter: Option<&Expr>, params: &HashMap<String, Value>)
+              -> Result<usize, String> {
+        let mut updates = vec![];
+        {
+            let evaluator = self.evaluator(params);
+            for i in self.matching_rows(&evaluator, filter)? {
+                let mut row = self.rows[i].clone();
+                for (column, expr) in assignments {
+                    let index = self.get_column_index(column).ok_or_else(|| format!("Unrecognized name: {}", column))?;
+                    row[index] = coerce(evaluator.eval(expr, Some(&self.rows[i]))?, &self.columns[index].type_)?;
+                }
+                updates.push((i, row));
+            }
+        }
+        let count = updates.len();
+        for (i, row) in updates {
+            self.rows[i] = row;
+        }
+        Ok(count)
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use google_bigquery2::api::{GetQueryResultsResponse, QueryRequest, QueryResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::executor::{GetQueryResultsRequest, QueryExecutor};
+
+/// One recorded request/response pair of a fixture file.
+///
+/// A fixture file is a JSON array of these, in the order the requests were sent. Queries are
+/// recorded with `request` and `response`, calls to `jobs.getQueryResults` (for further pages
+/// or slow jobs) with `results_request` and `results_response`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fixture {
+    #[serde(default)]
+    pub query: String,
+    #[serde(default)]
+    pub parameters: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request: Option<QueryRequest>,
+    /// The response, if the request succeeded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<QueryResponse>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub results_request: Option<GetQueryResultsRequest>,
+    /// The response, if the `jobs.getQueryResults` request succeeded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub results_response: Option<GetQueryResultsResponse>,
+    /// The error body BigQuery returned, if the request failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<serde_json::Value>,
+}
+
+impl Fixture {
+    fn key(&self) -> String {
+        match &self.results_request {
+            Some(results_request) => results_fixture_key(results_request),
+            None => fixture_key(&self.query, &self.parameters),
+        }
+    }
+}
+
+/// Passes every request on to another executor and writes the exchanges to a fixture file.
+///
+/// The file is rewritten after every request, so it is complete even if the test panics.
+/// Errors that are not BigQuery error responses (e.g. connection errors) are passed through
+/// without being recorded.
+pub struct RecordingExecutor {
+    inner: Box<dyn QueryExecutor>,
+    path: PathBuf,
+    fixtures: Mutex<Vec<Fixture>>,
+}
+
+impl RecordingExecutor {
+    /// Starts a new recording, replacing the file at `path` once the first request is made.
+    pub fn new(inner: Box<dyn QueryExecutor>, path: impl AsRef<Path>) -> Self {
+        Self {
+            inner,
+            path: path.as_ref().to_path_buf(),
+            fixtures: Mutex::new(vec![]),
+        }
+    }
+
+    fn record(&self, fixture: Fixture) -> Result<(), Error> {
+        let mut fixtures = self.fixtures.lock().unwrap();
+        fixtures.push(fixture);
+        let content = serde_json::to_string_pretty(&*fixtures)
+            .map_err(|e| Error::Other(format!("Failed to serialize fixtures: {}", e)))?;
+        std::fs::write(&self.path, content)
+            .map_err(|e| Error::Other(format!("Failed to write fixtures to {}: {}", self.path.display(), e)))?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl QueryExecutor for RecordingExecutor {
+    async fn query(&self, req: QueryRequest, project_id: &str) -> Result<QueryResponse, Error> {
+        let mut fixture = Fixture {
+            query: req.query.clone().unwrap_or_default(),
+            parameters: get_parameters(&req)?,
+            request: Some(req.clone()),
+            response: None,
+            results_request: None,
+            results_response: None,
+            error: None,
+        };
+        let result = self.inner.query(req, project_id).await;
+        match &result {
+            Ok(query_res) => fixture.response = Some(query_res.clone()),
+            Err(e) => match e.to_error_response() {
+                Some(value) => fixture.error = Some(value),
+                None => return result,
+            },
+        }
+        self.record(fixture)?;
+        result
+    }
+
+    async fn get_query_results(&self, req: GetQueryResultsRequest, project_id: &str) -> Result<GetQueryResultsResponse, Error> {
+        let mut fixture = Fixture {
+            query: String::new(),
+            parameters: serde_json::Value::Null,
+            request: None,
+            response: None,
+            results_request: Some(req.clone()),
+            results_response: None,
+            error: None,
+        };
+        let result = self.inner.get_query_results(req, project_id).await;
+        match &result {
+            Ok(results) => fixture.results_response = Some(results.clone()),
+            Err(e) => match e.to_error_response() {
+                Some(value) => fixture.error = Some(value),
+                None => return result,
+            },
+        }
+        self.record(fixture)?;
+        result
+    }
+}
+
+/// Answers requests from a fixture file written by a [`RecordingExecutor`], without any network access.
+///
+/// Requests are matched on their query text and parameters. If the same request was recorded
+/// more than once, the responses are returned in the recorded order and the last one is repeated
+/// after that. Requests that were never recorded fail.
+pub struct ReplayExecutor {
+    fixtures: Mutex<HashMap<String, ReplayQueue>>,
+}
+
+struct ReplayQueue {
+    fixtures: Vec<Fixture>,
+    next: usize,
+}
+
+impl ReplayExecutor {
+    pub fn new(fixtures: Vec<Fixture>) -> Self {
+        let mut queues: HashMap<String, ReplayQueue> = HashMap::new();
+        for fixture in fixtures {
+            queues.entry(fixture.key())
+                .or_insert_with(|| ReplayQueue { fixtures: vec![], next: 0 })
+                .fixtures.push(fixture);
+        }
+        Self {
+            fixtures: Mutex::new(queues),
+        }
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| Error::Other(format!("Failed to read fixtures from {}: {}", path.display(), e)))?;
+        let fixtures: Vec<Fixture> = serde_json::from_str(&content)
+            .map_err(|e| Error::Other(format!("Failed to parse fixtures from {}: {}", path.display(), e)))?;
+        Ok(Self::new(fixtures))
+    }
+}
+
+impl ReplayExecutor {
+    fn next_fixture(&self, key: &str) -> Option<Fixture> {
+        let mut queues = self.fixtures.lock().unwrap();
+        let queue = queues.get_mut(key)?;
+        let fixture = queue.fixtures[queue.next.min(queue.fixtures.len() - 1)].clone();
+        queue.next += 1;
+        Some(fixture)
+    }
+}
+
+#[async_trait]
+impl QueryExecutor for ReplayExecutor {
+    async fn query(&self, req: QueryRequest, _project_id: &str) -> Result<QueryResponse, Error> {
+        let query = req.query.clone().unwrap_or_default();
+        let parameters = get_parameters(&req)?;
+        let fixture = self.next_fixture(&fixture_key(&query, &parameters))
+            .ok_or_else(|| Error::Other(format!("No recorded response for query: {} with parameters: {}", query, parameters)))?;
+        match (fixture.response, fixture.error) {
+            (_, Some(error)) => Err(Error::from_error_response(&error)),
+            (Some(response), None) => Ok(response),
+            (None, None) => Err(Error::Other(format!("The recorded fixture for query: {} has neither a response nor an error", query))),
+        }
+    }
+
+    async fn get_query_results(&self, req: GetQueryResultsRequest, _project_id: &str) -> Result<GetQueryResultsResponse, Error> {
+        let fixture = self.next_fixture(&results_fixture_key(&req))
+            .ok_or_else(|| Error::Other(format!("No recorded results for job: {} with page token: {:?}", req.job_id, req.page_token)))?;
+        match (fixture.results_response, fixture.error) {
+            (_, Some(error)) => Err(Error::from_error_response(&error)),
+            (Some(results), None) => Ok(results),
+            (None, None) => Err(Error::Other(format!("The recorded fixture for job: {} has neither a response nor an error", req.job_id))),
+        }
+    }
+}
+
+/// The parameters as JSON value, which orders the keys of struct values, so they can be compared as text.
+fn get_parameters(req: &QueryRequest) -> Result<serde_json::Value, Error> {
+    serde_json::to_value(&req.query_parameters)
+        .map_err(|e| Error::Other(format!("Failed to serialize query parameters: {}", e)))
+}
+
+fn fixture_key(query: &str, parameters: &serde_json::Value) -> String {
+    format!("{}\n{}", query, parameters)
+}
+
+/// The timeout is left out, since it depends on how long the client has been waiting already.
+fn results_fixture_key(req: &GetQueryResultsRequest) -> String {
+    format!("jobs.getQueryResults\n{}\n{}", req.job_id, req.page_token.as_deref().unwrap_or_default())
+}
@@ -0,0 +1,8 @@
+//! Test doubles for code that works with a [`BigqueryClient`](crate::BigqueryClient).
+
+pub use fake_bigquery::FakeBigquery;
+pub use fixtures::{Fixture, RecordingExecutor, ReplayExecutor};
+
+mod fake_bigquery;
+mod fixtures;
+mod sql;
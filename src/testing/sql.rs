@@ -0,0 +1,625 @@
+//! A parser for the small part of GoogleSQL the [`FakeBigquery`](super::FakeBigquery) understands.
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    QuotedIdent(String),
+    Param(String),
+    Number(String),
+    String(String),
+    Symbol(&'static str),
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else if c == '@' {
+            let start = i + 1;
+            i += 1;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Param(chars[start..i].iter().collect()));
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            tokens.push(Token::Number(chars[start..i].iter().collect()));
+        } else if c == '\'' || c == '"' || c == '`' {
+            let quote = c;
+            let mut value = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(format!("Unterminated quote in query: {}", query)),
+                    Some('\\') if quote != '`' => {
+                        if let Some(escaped) = chars.get(i + 1) {
+                            value.push(*escaped);
+                        }
+                        i += 2;
+                    }
+                    Some(c) if *c == quote => {
+                        i += 1;
+                        break;
+                    }
+                    Some(c) => {
+                        value.push(*c);
+                        i += 1;
+                    }
+                }
+            }
+            tokens.push(match quote {
+                '`' => Token::QuotedIdent(value),
+                _ => Token::String(value),
+            });
+        } else {
+            let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+            let symbol = match two.as_str() {
+                "!=" => Some("!="),
+                "<>" => Some("!="),
+                "<=" => Some("<="),
+                ">=" => Some(">="),
+                _ => None,
+            };
+            if let Some(symbol) = symbol {
+                tokens.push(Token::Symbol(symbol));
+                i += 2;
+                continue;
+            }
+            let symbol = match c {
+                '(' => "(",
+                ')' => ")",
+                ',' => ",",
+                '.' => ".",
+                '*' => "*",
+                '=' => "=",
+                '<' => "<",
+                '>' => ">",
+                '+' => "+",
+                '-' => "-",
+                ';' => ";",
+                '[' => "[",
+                ']' => "]",
+                _ => return Err(format!("Unexpected character '{}' in query: {}", c, query)),
+            };
+            tokens.push(Token::Symbol(symbol));
+            i += 1;
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Literal {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expr {
+    Literal(Literal),
+    Param(String),
+    Column(String),
+    Star,
+    Function { name: String, distinct: bool, args: Vec<Expr> },
+    Binary { op: String, left: Box<Expr>, right: Box<Expr> },
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    IsNull { expr: Box<Expr>, negated: bool },
+    InList { expr: Box<Expr>, list: Vec<Expr>, negated: bool },
+    InUnnest { expr: Box<Expr>, array: Box<Expr>, negated: bool },
+    Between { expr: Box<Expr>, low: Box<Expr>, high: Box<Expr>, negated: bool },
+    Like { expr: Box<Expr>, pattern: Box<Expr>, negated: bool },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SelectItem {
+    pub expr: Expr,
+    pub alias: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Select {
+    pub distinct: bool,
+    pub items: Vec<SelectItem>,
+    /// `None` for a select without `FROM`, which returns exactly one row.
+    pub table: Option<String>,
+    pub filter: Option<Expr>,
+    pub order_by: Vec<(Expr, bool)>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Statement {
+    Select(Select),
+    Insert { table: String, columns: Vec<String>, rows: Vec<Vec<Expr>> },
+    Update { table: String, assignments: Vec<(String, Expr)>, filter: Option<Expr> },
+    Delete { table: String, filter: Expr },
+    Merge {
+        table: String,
+        alias: Option<String>,
+        /// The selects of the source, combined with `UNION ALL`.
+        source: Vec<Select>,
+        source_alias: Option<String>,
+        on: Expr,
+        clauses: Vec<MergeClause>,
+    },
+}
+
+/// A `WHEN [NOT] MATCHED [AND condition] THEN ..` clause of a `MERGE`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum MergeClause {
+    Update { condition: Option<Expr>, assignments: Vec<(String, Expr)> },
+    Insert { condition: Option<Expr>, columns: Vec<String>, values: Vec<Expr> },
+}
+
+pub(crate) fn parse(query: &str) -> Result<Statement, String> {
+    let tokens = tokenize(query)?;
+    let mut parser = Parser { tokens, pos: 0, query };
+    let statement = parser.parse_statement()?;
+    parser.eat_symbol(";");
+    if parser.pos < parser.tokens.len() {
+        return Err(parser.error("end of query"));
+    }
+    Ok(statement)
+}
+
+struct Parser<'q> {
+    tokens: Vec<Token>,
+    pos: usize,
+    query: &'q str,
+}
+
+impl<'q> Parser<'q> {
+    fn error(&self, expected: &str) -> String {
+        format!("Syntax error: Expected {} but got {:?} in query: {}", expected, self.tokens.get(self.pos), self.query)
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.tokens.get(self.pos), Some(Token::Ident(i)) if i.eq_ignore_ascii_case(keyword))
+    }
+
+    fn peek_keyword_at(&self, offset: usize, keyword: &str) -> bool {
+        matches!(self.tokens.get(self.pos + offset), Some(Token::Ident(i)) if i.eq_ignore_ascii_case(keyword))
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.peek_keyword(keyword) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), String> {
+        match self.eat_keyword(keyword) {
+            true => Ok(()),
+            false => Err(self.error(keyword)),
+        }
+    }
+
+    fn peek_symbol(&self, symbol: &str) -> bool {
+        matches!(self.tokens.get(self.pos), Some(Token::Symbol(s)) if *s == symbol)
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        if self.peek_symbol(symbol) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), String> {
+        match self.eat_symbol(symbol) {
+            true => Ok(()),
+            false => Err(self.error(symbol)),
+        }
+    }
+
+    fn parse_identifier(&mut self) -> Result<String, String> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Ident(i)) | Some(Token::QuotedIdent(i)) => {
+                let i = i.clone();
+                self.pos += 1;
+                Ok(i)
+            }
+            _ => Err(self.error("identifier")),
+        }
+    }
+
+    /// A possibly qualified name like `project.dataset.table`.
+    fn parse_path(&mut self) -> Result<String, String> {
+        let mut parts = vec![self.parse_identifier()?];
+        while self.eat_symbol(".") {
+            parts.push(self.parse_identifier()?);
+        }
+        Ok(parts.join("."))
+    }
+
+    fn parse_usize(&mut self) -> Result<usize, String> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Number(n)) => {
+                let n = n.parse().map_err(|_| self.error("integer"))?;
+                self.pos += 1;
+                Ok(n)
+            }
+            _ => Err(self.error("integer")),
+        }
+    }
+
+    fn parse_statement(&mut self) -> Result<Statement, String> {
+        if self.peek_keyword("select") {
+            Ok(Statement::Select(self.parse_select()?))
+        } else if self.eat_keyword("insert") {
+            self.eat_keyword("into");
+            self.parse_insert()
+        } else if self.eat_keyword("update") {
+            self.parse_update()
+        } else if self.eat_keyword("delete") {
+            self.eat_keyword("from");
+            let table = self.parse_path()?;
+            // like BigQuery, a DELETE needs a WHERE clause
+            self.expect_keyword("where")?;
+            let filter = self.parse_expr()?;
+            Ok(Statement::Delete { table, filter })
+        } else if self.eat_keyword("merge") {
+            self.eat_keyword("into");
+            self.parse_merge()
+        } else {
+            Err(self.error("SELECT, INSERT, UPDATE, DELETE or MERGE"))
+        }
+    }
+
+    fn parse_select(&mut self) -> Result<Select, String> {
+        self.expect_keyword("select")?;
+        let distinct = self.eat_keyword("distinct");
+        let mut items = vec![];
+        loop {
+            let expr = match self.eat_symbol("*") {
+                true => Expr::Star,
+                false => self.parse_expr()?,
+            };
+            let alias = match self.eat_keyword("as") {
+                true => Some(self.parse_identifier()?),
+                false => None,
+            };
+            items.push(SelectItem { expr, alias });
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+        let table = match self.eat_keyword("from") {
+            true => Some(self.parse_path()?),
+            false => None,
+        };
+        let filter = match self.eat_keyword("where") {
+            true => Some(self.parse_expr()?),
+            false => None,
+        };
+        let mut order_by = vec![];
+        if self.eat_keyword("order") {
+            self.expect_keyword("by")?;
+            loop {
+                let expr = self.parse_expr()?;
+                let ascending = match self.eat_keyword("desc") {
+                    true => false,
+                    false => {
+                        self.eat_keyword("asc");
+                        true
+                    }
+                };
+                order_by.push((expr, ascending));
+                if !self.eat_symbol(",") {
+                    break;
+                }
+            }
+        }
+        let limit = match self.eat_keyword("limit") {
+            true => Some(self.parse_usize()?),
+            false => None,
+        };
+        let offset = match self.eat_keyword("offset") {
+            true => Some(self.parse_usize()?),
+            false => None,
+        };
+        Ok(Select { distinct, items, table, filter, order_by, limit, offset })
+    }
+
+    fn parse_insert(&mut self) -> Result<Statement, String> {
+        let table = self.parse_path()?;
+        self.expect_symbol("(")?;
+        let mut columns = vec![];
+        loop {
+            columns.push(self.parse_identifier()?);
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+        self.expect_symbol(")")?;
+        self.expect_keyword("values")?;
+        let mut rows = vec![];
+        loop {
+            self.expect_symbol("(")?;
+            let values = self.parse_expr_list()?;
+            self.expect_symbol(")")?;
+            if values.len() != columns.len() {
+                return Err(format!("Inserted row has wrong column count; Has {}, expected {}", values.len(), columns.len()));
+            }
+            rows.push(values);
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+        Ok(Statement::Insert { table, columns, rows })
+    }
+
+    fn parse_update(&mut self) -> Result<Statement, String> {
+        let table = self.parse_path()?;
+        self.expect_keyword("set")?;
+        let assignments = self.parse_assignments()?;
+        let filter = match self.eat_keyword("where") {
+            true => Some(self.parse_expr()?),
+            false => None,
+        };
+        Ok(Statement::Update { table, assignments, filter })
+    }
+
+    fn parse_merge(&mut self) -> Result<Statement, String> {
+        let table = self.parse_path()?;
+        let alias = self.parse_alias(&["using"])?;
+        self.expect_keyword("using")?;
+        self.expect_symbol("(")?;
+        let mut source = vec![self.parse_select()?];
+        while self.eat_keyword("union") {
+            self.expect_keyword("all")?;
+            source.push(self.parse_select()?);
+        }
+        self.expect_symbol(")")?;
+        let source_alias = self.parse_alias(&["on"])?;
+        self.expect_keyword("on")?;
+        let on = self.parse_expr()?;
+        let mut clauses = vec![];
+        while self.eat_keyword("when") {
+            let matched = !self.eat_keyword("not");
+            self.expect_keyword("matched")?;
+            if !matched && self.eat_keyword("by") {
+                self.expect_keyword("target")?;
+            }
+            let condition = match self.eat_keyword("and") {
+                true => Some(self.parse_expr()?),
+                false => None,
+            };
+            self.expect_keyword("then")?;
+            if matched {
+                self.expect_keyword("update")?;
+                self.expect_keyword("set")?;
+                clauses.push(MergeClause::Update { condition, assignments: self.parse_assignments()? });
+            } else {
+                self.expect_keyword("insert")?;
+                self.expect_symbol("(")?;
+                let mut columns = vec![];
+                loop {
+                    columns.push(self.parse_identifier()?);
+                    if !self.eat_symbol(",") {
+                        break;
+                    }
+                }
+                self.expect_symbol(")")?;
+                self.expect_keyword("values")?;
+                self.expect_symbol("(")?;
+                let values = self.parse_expr_list()?;
+                self.expect_symbol(")")?;
+                if values.len() != columns.len() {
+                    return Err(format!("Inserted row has wrong column count; Has {}, expected {}", values.len(), columns.len()));
+                }
+                clauses.push(MergeClause::Insert { condition, columns, values });
+            }
+        }
+        if clauses.is_empty() {
+            return Err(self.error("WHEN"));
+        }
+        Ok(Statement::Merge { table, alias, source, source_alias, on, clauses })
+    }
+
+    /// An optional `[AS] alias`, as long as the next token is not one of the keywords that follow it.
+    fn parse_alias(&mut self, followed_by: &[&str]) -> Result<Option<String>, String> {
+        if self.eat_keyword("as") {
+            return self.parse_identifier().map(Some);
+        }
+        match self.tokens.get(self.pos) {
+            Some(Token::Ident(_)) | Some(Token::QuotedIdent(_)) if !followed_by.iter().any(|k| self.peek_keyword(k)) =>
+                self.parse_identifier().map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn parse_assignments(&mut self) -> Result<Vec<(String, Expr)>, String> {
+        let mut assignments = vec![];
+        loop {
+            let column = self.parse_path()?;
+            self.expect_symbol("=")?;
+            assignments.push((column, self.parse_expr()?));
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+        Ok(assignments)
+    }
+
+    fn parse_expr_list(&mut self) -> Result<Vec<Expr>, String> {
+        let mut list = vec![];
+        loop {
+            list.push(self.parse_expr()?);
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+        Ok(list)
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, String> {
+        self.parse_or()
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_and()?;
+        while self.eat_keyword("or") {
+            let right = self.parse_and()?;
+            left = Expr::Binary { op: "or".to_string(), left: Box::new(left), right: Box::new(right) };
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_not()?;
+        while self.eat_keyword("and") {
+            let right = self.parse_not()?;
+            left = Expr::Binary { op: "and".to_string(), left: Box::new(left), right: Box::new(right) };
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, String> {
+        match self.eat_keyword("not") {
+            true => Ok(Expr::Not(Box::new(self.parse_not()?))),
+            false => self.parse_comparison(),
+        }
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, String> {
+        let left = self.parse_additive()?;
+        for op in ["=", "!=", "<=", ">=", "<", ">"] {
+            if self.eat_symbol(op) {
+                let right = self.parse_additive()?;
+                return Ok(Expr::Binary { op: op.to_string(), left: Box::new(left), right: Box::new(right) });
+            }
+        }
+        if self.eat_keyword("is") {
+            let negated = self.eat_keyword("not");
+            self.expect_keyword("null")?;
+            return Ok(Expr::IsNull { expr: Box::new(left), negated });
+        }
+        let negated = self.peek_keyword("not")
+            && (self.peek_keyword_at(1, "in") || self.peek_keyword_at(1, "between") || self.peek_keyword_at(1, "like"));
+        if negated {
+            self.pos += 1;
+        }
+        if self.eat_keyword("in") {
+            if self.eat_keyword("unnest") {
+                self.expect_symbol("(")?;
+                let array = self.parse_expr()?;
+                self.expect_symbol(")")?;
+                return Ok(Expr::InUnnest { expr: Box::new(left), array: Box::new(array), negated });
+            }
+            self.expect_symbol("(")?;
+            let list = self.parse_expr_list()?;
+            self.expect_symbol(")")?;
+            return Ok(Expr::InList { expr: Box::new(left), list, negated });
+        }
+        if self.eat_keyword("between") {
+            let low = self.parse_additive()?;
+            self.expect_keyword("and")?;
+            let high = self.parse_additive()?;
+            return Ok(Expr::Between { expr: Box::new(left), low: Box::new(low), high: Box::new(high), negated });
+        }
+        if self.eat_keyword("like") {
+            let pattern = self.parse_additive()?;
+            return Ok(Expr::Like { expr: Box::new(left), pattern: Box::new(pattern), negated });
+        }
+        if negated {
+            return Err(self.error("IN, BETWEEN or LIKE"));
+        }
+        Ok(left)
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let op = match () {
+                _ if self.eat_symbol("+") => "+",
+                _ if self.eat_symbol("-") => "-",
+                _ => break,
+            };
+            let right = self.parse_unary()?;
+            left = Expr::Binary { op: op.to_string(), left: Box::new(left), right: Box::new(right) };
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        match self.eat_symbol("-") {
+            true => Ok(Expr::Negate(Box::new(self.parse_primary()?))),
+            false => self.parse_primary(),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        let token = self.tokens.get(self.pos).cloned().ok_or_else(|| self.error("expression"))?;
+        match token {
+            Token::Symbol("(") => {
+                self.pos += 1;
+                let expr = self.parse_expr()?;
+                self.expect_symbol(")")?;
+                Ok(expr)
+            }
+            Token::Param(name) => {
+                self.pos += 1;
+                Ok(Expr::Param(name))
+            }
+            Token::String(value) => {
+                self.pos += 1;
+                Ok(Expr::Literal(Literal::String(value)))
+            }
+            Token::Number(value) => {
+                self.pos += 1;
+                let literal = match value.contains('.') {
+                    true => value.parse().ok().map(Literal::Float),
+                    false => value.parse().ok().map(Literal::Int),
+                };
+                literal.map(Expr::Literal).ok_or_else(|| self.error("number"))
+            }
+            Token::Ident(ident) if ident.eq_ignore_ascii_case("null") => {
+                self.pos += 1;
+                Ok(Expr::Literal(Literal::Null))
+            }
+            Token::Ident(ident) if ident.eq_ignore_ascii_case("true") || ident.eq_ignore_ascii_case("false") => {
+                self.pos += 1;
+                Ok(Expr::Literal(Literal::Bool(ident.eq_ignore_ascii_case("true"))))
+            }
+            Token::Ident(_) | Token::QuotedIdent(_) => {
+                let path = self.parse_path()?;
+                if !self.eat_symbol("(") {
+                    return Ok(Expr::Column(path));
+                }
+                let distinct = self.eat_keyword("distinct");
+                let args = match () {
+                    _ if self.eat_symbol(")") => return Ok(Expr::Function { name: path.to_lowercase(), distinct, args: vec![] }),
+                    _ if self.eat_symbol("*") => vec![Expr::Star],
+                    _ => self.parse_expr_list()?,
+                };
+                self.expect_symbol(")")?;
+                Ok(Expr::Function { name: path.to_lowercase(), distinct, args })
+            }
+            _ => Err(self.error("expression")),
+        }
+    }
+}
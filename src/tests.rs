@@ -3,10 +3,13 @@ use google_bigquery_derive::HasBigQueryClient;
 
 use crate::data::{BigDataTable, BigDataTableBase, BigDataTableBaseConvenience};
 use crate::client::HasBigQueryClient;
-use crate::utils::BigDataValueType;
+use crate::utils::{BigDataValueType, BigDataVersionType, Timestamp};
 
 
 use crate::utils::ConvertValueToBigqueryParamValue;
+use google_bigquery2::api::{QueryRequest, QueryResponse};
+use crate::testing::FakeBigquery;
+use futures::StreamExt;
 
 use super::*;
 
@@ -98,9 +101,1236 @@ async fn from_pk() {
     assert_eq!(i1.yes, None);
 }
 
+//region offline tests against the fake
+
+#[tokio::test]
+async fn fake_save() {
+    let (fake, client) = get_fake_client().await;
+
+    let mut q = Infos::load_by_field(&client, stringify!(info1), Some("a".to_string()), 10).await.unwrap();
+    assert_eq!(q.len(), 1);
+
+    let i1 = &mut q[0];
+    assert_eq!(i1.row_id, 3);
+    assert_eq!(i1.info2, None);
+
+    i1.info2 = Some("b".to_string());
+    i1.save_to_bigquery().await.unwrap();
+    i1.info2 = Some("c".to_string());
+    i1.load_from_bigquery().await.unwrap();
+    assert_eq!(i1.info2, Some("b".to_string()));
+
+    i1.info2 = None;
+    i1.save_to_bigquery().await.unwrap();
+    i1.load_from_bigquery().await.unwrap();
+    assert_eq!(i1.row_id, 3);
+    assert_eq!(i1.info3, Some("c".to_string()));
+    assert_eq!(i1.info2, None);
+
+    let mut i2 = Infos::create_with_pk(&client, "42".to_string());
+    i2.row_id = 42;
+    i2.yes = Some(false);
+    i2.save_to_bigquery().await.unwrap();
+    let rows = fake.get_rows("testrustproject-372221.test1.Infos");
+    assert_eq!(rows.len(), 6);
+    let row = rows.iter().find(|r| r["row_ids"].as_deref() == Some("42")).unwrap();
+    assert_eq!(row["Id"].as_deref(), Some("42"));
+    assert_eq!(row["yes"].as_deref(), Some("false"));
+}
+
+#[tokio::test]
+async fn fake_save_with_merge() {
+    let (fake, _) = get_fake_client().await;
+    let client = BigqueryClient::builder()
+        .project_id("testrustproject-372221")
+        .dataset_id("test1")
+        .executor(fake.clone())
+        .save_mode(SaveMode::Merge)
+        .build().await.unwrap();
+
+    let mut i1 = Infos::load_from_pk(&client, "3".to_string()).await.unwrap().unwrap();
+    i1.info2 = Some("b".to_string());
+    i1.info1 = None;
+    i1.save_to_bigquery().await.unwrap();
+
+    let mut i2 = Infos::create_with_pk(&client, "42".to_string());
+    i2.row_id = 42;
+    i2.yes = Some(false);
+    i2.save_to_bigquery().await.unwrap();
+
+    let requests = fake.get_requests();
+    assert_eq!(requests.len(), 3);
+    assert!(requests[1..].iter().all(|r| r.query.as_ref().unwrap().starts_with("merge into")));
+
+    let rows = fake.get_rows("testrustproject-372221.test1.Infos");
+    assert_eq!(rows.len(), 6);
+    let row = rows.iter().find(|r| r["row_ids"].as_deref() == Some("3")).unwrap();
+    assert_eq!(row["info"].as_deref(), Some("b"));
+    assert_eq!(row["info1"], None);
+    assert_eq!(row["info3"].as_deref(), Some("c"));
+    let row = rows.iter().find(|r| r["row_ids"].as_deref() == Some("42")).unwrap();
+    assert_eq!(row["Id"].as_deref(), Some("42"));
+    assert_eq!(row["yes"].as_deref(), Some("false"));
+}
+
+#[tokio::test]
+async fn save_many() {
+    let (fake, client) = get_fake_client().await;
+    let mut i1 = Infos::load_from_pk(&client, "3".to_string()).await.unwrap().unwrap();
+    i1.info2 = Some("b".to_string());
+    let mut infos = vec![i1];
+    for id in [42, 43] {
+        let mut info = Infos::create_with_pk(&client, id.to_string());
+        info.row_id = id;
+        infos.push(info);
+    }
+    assert_eq!(Infos::save_many_to_bigquery(&client, &infos).await.unwrap(), 3);
+    assert_eq!(fake.get_requests().len(), 2);
+
+    let rows = fake.get_rows("testrustproject-372221.test1.Infos");
+    assert_eq!(rows.len(), 7);
+    let row = rows.iter().find(|r| r["row_ids"].as_deref() == Some("3")).unwrap();
+    assert_eq!(row["info"].as_deref(), Some("b"));
+    assert_eq!(row["info1"].as_deref(), Some("a"));
+    assert!(rows.iter().any(|r| r["Id"].as_deref() == Some("43")));
+
+    assert_eq!(Infos::save_many_to_bigquery(&client, &[]).await.unwrap(), 0);
+    assert_eq!(fake.get_requests().len(), 2);
+}
+
+#[tokio::test]
+async fn save_many_splits_statements_at_the_parameter_limit() {
+    let (fake, client) = get_fake_client().await;
+    // 7 parameters per row, so at most 1428 rows fit into one statement
+    let infos: Vec<Infos> = (1000..2500)
+        .map(|id| {
+            let mut info = Infos::create_with_pk(&client, id.to_string());
+            info.row_id = id;
+            info
+        })
+        .collect();
+    assert_eq!(Infos::save_many_to_bigquery(&client, &infos).await.unwrap(), 1500);
+
+    let requests = fake.get_requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].query_parameters.as_ref().unwrap().len(), 1428 * 7);
+    assert_eq!(fake.get_rows("testrustproject-372221.test1.Infos").len(), 1505);
+}
+
+#[tokio::test]
+async fn save_many_splits_statements_at_the_request_size_limit() {
+    let (fake, client) = get_fake_client().await;
+    // far below the parameter and query length limits, but 25 MB of parameter values
+    let infos: Vec<Infos> = (1000..1025)
+        .map(|id| {
+            let mut info = Infos::create_with_pk(&client, id.to_string());
+            info.row_id = id;
+            info.info1 = Some("x".repeat(1_000_000));
+            info
+        })
+        .collect();
+    assert_eq!(Infos::save_many_to_bigquery(&client, &infos).await.unwrap(), 25);
+
+    let requests = fake.get_requests();
+    assert_eq!(requests.len(), 3);
+    for request in &requests {
+        assert!(serde_json::to_vec(request).unwrap().len() < 10_000_000);
+    }
+    assert_eq!(fake.get_rows("testrustproject-372221.test1.Infos").len(), 30);
+}
+
+#[tokio::test]
+async fn delete() {
+    let (fake, client) = get_fake_client().await;
+    let table = "testrustproject-372221.test1.Infos";
+
+    let i1 = Infos::load_from_pk(&client, "3".to_string()).await.unwrap().unwrap();
+    assert_eq!(i1.delete_from_bigquery().await.unwrap(), 1);
+    assert_eq!(i1.delete_from_bigquery().await.unwrap(), 0);
+    assert!(Infos::load_from_pk(&client, "3".to_string()).await.unwrap().is_none());
+
+    assert_eq!(Infos::delete_by_field(&client, stringify!(info3), Some("cc".to_string())).await.unwrap(), 2);
+    assert_eq!(fake.get_rows(table).len(), 2);
+
+    assert_eq!(Infos::delete_by_field::<bool>(&client, stringify!(yes), None).await.unwrap(), 0);
+    assert!(matches!(Infos::delete_by_field(&client, "nope", Some(1)).await, Err(Error::UnknownField { .. })));
+
+    let deleted = Infos::delete_by_custom_query(&client, "Id > @__min", vec![Infos::get_query_param("min", &Some(5i64))]).await;
+    assert_eq!(deleted.unwrap(), 1);
+    let rows = fake.get_rows(table);
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["Id"].as_deref(), Some("1"));
+}
+
+#[tokio::test]
+async fn insert_and_update() {
+    let (fake, client) = get_fake_client().await;
+    let table = "testrustproject-372221.test1.Infos";
+
+    let mut i1 = Infos::create_with_pk(&client, "42".to_string());
+    i1.row_id = 42;
+    assert!(matches!(i1.update_in_bigquery().await, Err(Error::NotFound(_))));
+    assert_eq!(i1.insert_to_bigquery().await.unwrap(), 1);
+    assert!(matches!(i1.insert_to_bigquery().await, Err(Error::DuplicatePrimaryKey { .. })));
+    assert_eq!(fake.get_rows(table).len(), 6);
+
+    i1.info1 = Some("x".to_string());
+    assert_eq!(i1.update_in_bigquery().await.unwrap(), 1);
+    let row = fake.get_rows(table).into_iter().find(|r| r["row_ids"].as_deref() == Some("42")).unwrap();
+    assert_eq!(row["info1"].as_deref(), Some("x"));
+    // one statement per call, no existence checks
+    assert_eq!(fake.get_requests().len(), 4);
+}
+
+#[tokio::test]
+async fn update_only_changed_fields() {
+    let (fake, client) = get_fake_client().await;
+    let mut i1 = Infos::load_from_pk(&client, "3".to_string()).await.unwrap().unwrap();
+    i1.save_to_bigquery().await.unwrap();
+    assert_eq!(i1.update_in_bigquery().await.unwrap(), 0);
+    // nothing is written, the update only checks that the row still exists
+    assert_eq!(fake.get_requests().len(), 2);
+
+    // another writer changes a column in the meantime
+    let mut other = Infos::load_from_pk(&client, "3".to_string()).await.unwrap().unwrap();
+    other.info3 = Some("other".to_string());
+    other.save_to_bigquery().await.unwrap();
+
+    i1.info1 = Some("changed".to_string());
+    i1.save_to_bigquery().await.unwrap();
+    let update = fake.get_requests().pop().unwrap();
+    assert!(update.query.as_ref().unwrap().contains("set info1 = @__info1 where"), "{:?}", update.query);
+    assert_eq!(update.query_parameters.as_ref().unwrap().len(), 2);
+
+    let row = fake.get_rows("testrustproject-372221.test1.Infos").into_iter().find(|r| r["row_ids"].as_deref() == Some("3")).unwrap();
+    assert_eq!(row["info1"].as_deref(), Some("changed"));
+    assert_eq!(row["info3"].as_deref(), Some("other"));
+
+    // saved values are the new snapshot
+    let requests = fake.get_requests().len();
+    i1.save_to_bigquery().await.unwrap();
+    assert_eq!(fake.get_requests().len(), requests);
+}
+
+#[tokio::test]
+async fn version_conflicts() {
+    let (fake, client) = get_fake_client().await;
+    let table = "testrustproject-372221.test1.Versioned";
+    fake.create_table(table, &[("id", "STRING"), ("version", "INT64"), ("value", "STRING")]);
+
+    let mut row = Versioned::create_with_pk(&client, "a".to_string());
+    row.save_to_bigquery().await.unwrap();
+    assert_eq!(row.version, 1);
+    let mut stale = Versioned::load_from_pk(&client, "a".to_string()).await.unwrap().unwrap();
+
+    row.value = Some("new".to_string());
+    row.save_to_bigquery().await.unwrap();
+    assert_eq!(row.version, 2);
+
+    stale.value = Some("stale".to_string());
+    assert!(matches!(stale.save_to_bigquery().await, Err(Error::VersionConflict { .. })));
+    assert_eq!(stale.version, 1);
+    assert!(matches!(stale.update_in_bigquery().await, Err(Error::VersionConflict { .. })));
+    assert_eq!(stale.version, 1);
+    let rows = fake.get_rows(table);
+    assert_eq!(rows[0]["value"].as_deref(), Some("new"));
+    assert_eq!(rows[0]["version"].as_deref(), Some("2"));
+
+    let mut missing = Versioned::create_with_pk(&client, "b".to_string());
+    assert!(matches!(missing.update_in_bigquery().await, Err(Error::NotFound(_))));
+    assert!(matches!(Versioned::save_many_to_bigquery(&client, &[row]).await, Err(Error::Other(_))));
+
+    let client = BigqueryClient::builder()
+        .project_id("testrustproject-372221")
+        .dataset_id("test1")
+        .executor(fake.clone())
+        .save_mode(SaveMode::Merge)
+        .build().await.unwrap();
+    let mut row = Versioned::load_from_pk(&client, "a".to_string()).await.unwrap().unwrap();
+    row.save_to_bigquery().await.unwrap();
+    assert_eq!(row.version, 3);
+    assert_eq!(fake.get_rows(table)[0]["version"].as_deref(), Some("3"));
+    let mut stale = Versioned { client: Some(&client), version: 2, ..Default::default() };
+    stale.id = "a".to_string();
+    assert!(matches!(stale.save_to_bigquery().await, Err(Error::VersionConflict { .. })));
+    assert_eq!(stale.version, 2);
+}
+
+#[tokio::test]
+async fn timestamp_version_conflicts() {
+    let (fake, client) = get_fake_client().await;
+    let table = "testrustproject-372221.test1.TimestampVersioned";
+    fake.create_table(table, &[("id", "STRING"), ("changed", "TIMESTAMP"), ("value", "STRING")]);
+
+    let mut row = TimestampVersioned::create_with_pk(&client, "a".to_string());
+    row.save_to_bigquery().await.unwrap();
+    let first = row.changed;
+    assert!(first > Timestamp::default());
+    // BigQuery returns the seconds since the epoch, which have to give back the same microseconds
+    assert!(fake.get_rows(table)[0]["changed"].as_deref().unwrap().contains('E'));
+    let mut stale = TimestampVersioned::load_from_pk(&client, "a".to_string()).await.unwrap().unwrap();
+    assert_eq!(stale.changed, first);
+
+    row.value = Some("new".to_string());
+    row.save_to_bigquery().await.unwrap();
+    assert!(row.changed > first);
+    let loaded = TimestampVersioned::load_from_pk(&client, "a".to_string()).await.unwrap().unwrap();
+    assert_eq!(loaded.changed, row.changed);
+
+    stale.value = Some("stale".to_string());
+    assert!(matches!(stale.save_to_bigquery().await, Err(Error::VersionConflict { .. })));
+    assert_eq!(stale.changed, first);
+    assert_eq!(fake.get_rows(table)[0]["value"].as_deref(), Some("new"));
+
+    let client = BigqueryClient::builder()
+        .project_id("testrustproject-372221")
+        .dataset_id("test1")
+        .executor(fake.clone())
+        .save_mode(SaveMode::Merge)
+        .build().await.unwrap();
+    stale.client = Some(&client);
+    assert!(matches!(stale.save_to_bigquery().await, Err(Error::VersionConflict { .. })));
+    let mut row = TimestampVersioned::load_from_pk(&client, "a".to_string()).await.unwrap().unwrap();
+    let second = row.changed;
+    row.save_to_bigquery().await.unwrap();
+    assert!(row.changed > second);
+}
+
+#[test]
+fn timestamp_round_trip() {
+    let timestamp = Timestamp::from_micros(1_696_172_800_123_456).unwrap();
+    assert_eq!(timestamp.to_bigquery_param_value(), "2023-10-01 15:06:40.123456+00:00");
+    for value in ["1.696172800123456E9", "1696172800.123456", "2023-10-01 15:06:40.123456+00:00", "2023-10-01T15:06:40.123456Z"] {
+        assert_eq!(Timestamp::from_bigquery_value(&value.to_string()).unwrap(), timestamp, "{}", value);
+    }
+    assert!(timestamp.next_version() > timestamp);
+    let future = Timestamp::from(chrono::Utc::now() + chrono::Duration::days(1));
+    assert_eq!(future.next_version().micros(), future.micros() + 1);
+}
+
+#[tokio::test]
+async fn update_without_changes_checks_the_row() {
+    let (fake, client) = get_fake_client().await;
+    let mut row = Infos::load_from_pk(&client, "19".to_string()).await.unwrap().unwrap();
+    let requests = fake.get_requests().len();
+    assert_eq!(row.update_in_bigquery().await.unwrap(), 0);
+    // only the existence is checked, nothing is written
+    let query = fake.get_requests()[requests].query.clone().unwrap();
+    assert!(query.starts_with("select count(*)"), "{}", query);
+    Infos::delete_by_field(&client, stringify!(row_ids), Some("19".to_string())).await.unwrap();
+    assert!(matches!(row.update_in_bigquery().await, Err(Error::NotFound(_))));
+
+    fake.create_table("testrustproject-372221.test1.Versioned", &[("id", "STRING"), ("version", "INT64"), ("value", "STRING")]);
+    let mut row = SnapshotVersioned::create_with_pk(&client, "a".to_string());
+    row.save_to_bigquery().await.unwrap();
+    let mut stale = SnapshotVersioned::load_from_pk(&client, "a".to_string()).await.unwrap().unwrap();
+    assert_eq!(stale.update_in_bigquery().await.unwrap(), 0);
+    row.value = Some("new".to_string());
+    row.save_to_bigquery().await.unwrap();
+    assert!(matches!(stale.update_in_bigquery().await, Err(Error::VersionConflict { .. })));
+    assert_eq!(stale.version, 1);
+}
+
+#[tokio::test]
+async fn fake_load_by_field() {
+    let (_, client) = get_fake_client().await;
+
+    let q = Infos::load_by_field(&client, stringify!(info1), Some("a".to_string()), 10).await.unwrap();
+    assert_eq!(q.len(), 1);
+    assert_eq!(q[0].row_id, 3);
+    assert_eq!(q[0].info3, Some("c".to_string()));
+
+    let mut q = Infos::load_by_field(&client, stringify!(yes), Some(true), 10).await.unwrap();
+    q.sort_by_key(|i| i.row_id);
+    assert_eq!(q.iter().map(|i| i.row_id).collect::<Vec<i64>>(), vec![1, 19, 123123]);
+    assert_eq!(q[0].info3, Some("x3".to_string()));
+    assert_eq!(q[0].yes, Some(true));
+
+    let q = Infos::load_by_field(&client, stringify!(yes), Some(true), 2).await.unwrap();
+    assert_eq!(q.len(), 2);
+
+    let q = Infos::load_by_field(&client, stringify!(info1), Some("aosdinsofnpsngusn".to_string()), 10).await.unwrap();
+    assert_eq!(q.len(), 0);
+
+    let q = Infos::load_by_field::<bool>(&client, stringify!(yes), None, 10).await.unwrap();
+    assert_eq!(q.len(), 1);
+}
+
+#[tokio::test]
+async fn query_builder() {
+    let (fake, client) = get_fake_client().await;
+    let ids = |rows: Vec<Infos>| rows.iter().map(|r| r.row_id).collect::<Vec<i64>>();
+
+    let filter = Filter::all()
+        .eq(Infos::COLUMNS.yes, true)
+        .group(Filter::any().like(Infos::COLUMNS.info3, "c%").lt(Infos::COLUMNS.row_id, 5));
+    let rows = Infos::query(&client).filter(filter.clone()).order_by(Infos::COLUMNS.row_id, Order::Desc).limit(2).load().await.unwrap();
+    assert_eq!(ids(rows), vec![123123, 19]);
+    let request = fake.get_requests().pop().unwrap();
+    assert!(request.query.as_ref().unwrap().ends_with(
+        "where (yes = @__yes_0 and (info3 like @__info3_1 or Id < @__Id_2)) order by Id desc limit 2"), "{:?}", request.query);
+    let rows = Infos::query(&client).filter(filter).order_by("row_id", Order::Desc).limit(2).offset(1).load().await.unwrap();
+    assert_eq!(ids(rows), vec![19, 1]);
+
+    let rows = Infos::query(&client).filter(Filter::all().between(UntypedField::new("row_id"), 2, 20).ne(UntypedField::new("row_id"), 19)).order_by("row_id", Order::Asc)
+        .load().await.unwrap();
+    assert_eq!(ids(rows), vec![3, 7]);
+    let rows = Infos::query(&client).filter(Filter::all().is_in(UntypedField::new("row_id"), vec![1, 7, 8])).order_by("row_id", Order::Asc)
+        .load().await.unwrap();
+    assert_eq!(ids(rows), vec![1, 7]);
+    let rows = Infos::query(&client).filter(Filter::all().is_not_null("int_info4")).load().await.unwrap();
+    assert_eq!(ids(rows), vec![7]);
+    let rows = Infos::query(&client).filter(Filter::all().is_in::<i64>(UntypedField::new("row_id"), vec![])).load().await.unwrap();
+    assert!(rows.is_empty());
+    let rows: Vec<Infos> = Infos::query(&client).stream().collect::<Vec<_>>().await.into_iter().collect::<Result<_, _>>().unwrap();
+    assert_eq!(rows.len(), 5);
+
+    assert!(matches!(Infos::query(&client).filter(Filter::all().is_null("nope")).load().await, Err(Error::UnknownField { .. })));
+    assert!(matches!(Infos::query(&client).offset(1).load().await, Err(Error::Other(_))));
+}
+
+#[tokio::test]
+async fn typed_columns() {
+    let (_, client) = get_fake_client().await;
+    assert_eq!(Infos::COLUMNS.info2.name(), "info2");
+
+    let rows = Infos::load_by_column(&client, Infos::COLUMNS.info3, Some("cc".to_string()), 10).await.unwrap();
+    assert_eq!(rows.len(), 2);
+    let rows = Infos::query(&client)
+        .filter(Filter::all().is_in(Infos::COLUMNS.int_info4, vec![7]).is_not_null(Infos::COLUMNS.yes))
+        .load().await.unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].row_id, 7);
+}
+
+#[tokio::test]
+async fn aggregates() {
+    let (fake, client) = get_fake_client().await;
+    assert_eq!(Infos::count(&client).await.unwrap(), 5);
+    assert_eq!(Infos::count_by_field(&client, stringify!(info3), Some("cc".to_string())).await.unwrap(), 2);
+    assert_eq!(Infos::count_by_field::<String>(&client, stringify!(info1), None).await.unwrap(), 4);
+    assert!(Infos::exists_by_pk(&client, "19".to_string()).await.unwrap());
+    assert!(!Infos::exists_by_pk(&client, "20".to_string()).await.unwrap());
+
+    let mut values = Infos::distinct_values::<bool>(&client, stringify!(yes)).await.unwrap();
+    values.sort();
+    assert_eq!(values, vec![None, Some(false), Some(true)]);
+    assert!(matches!(Infos::distinct_values::<i64>(&client, stringify!(info3)).await, Err(Error::Parse { .. })));
+    assert!(matches!(Infos::count_by_field(&client, "nope", Some(1)).await, Err(Error::UnknownField { .. })));
+    // only counts and single values are fetched
+    assert!(fake.get_requests().iter().all(|r| !r.query.as_ref().unwrap().contains("info1, info3")));
+}
+
+#[tokio::test]
+async fn load_by_array_param() {
+    let (fake, client) = get_fake_client().await;
+    let mut rows = Infos::load_many_from_pk(&client, vec!["19".to_string(), "3".to_string(), "20".to_string()]).await.unwrap();
+    rows.sort_by(|a, b| a.row_ids.cmp(&b.row_ids));
+    assert_eq!(rows.iter().map(|r| r.row_ids.as_str()).collect::<Vec<_>>(), vec!["19", "3"]);
+    let request = fake.get_requests().pop().unwrap();
+    assert!(request.query.unwrap().ends_with("where row_ids in unnest(@__ids)"));
+    let param = &request.query_parameters.unwrap()[0];
+    let param_type = param.parameter_type.as_ref().unwrap();
+    assert_eq!(param_type.type_.as_deref(), Some("ARRAY"));
+    assert_eq!(param_type.array_type.clone().flatten().unwrap().type_.as_deref(), Some("STRING"));
+    assert_eq!(param.parameter_value.as_ref().unwrap().array_values.as_ref().unwrap().len(), 3);
+
+    let rows = Infos::load_by_field_in(&client, stringify!(row_id), vec![1i64, 7]).await.unwrap();
+    assert_eq!(rows.len(), 2);
+    assert!(rows.iter().all(|r| r.row_id == 1 || r.row_id == 7));
+
+    let requests = fake.get_requests().len();
+    assert!(Infos::load_many_from_pk(&client, vec![]).await.unwrap().is_empty());
+    assert_eq!(fake.get_requests().len(), requests);
+}
+
+#[tokio::test]
+async fn struct_params() {
+    let (_fake, client) = get_fake_client().await;
+    let row = Infos::load_from_pk(&client, "19".to_string()).await.unwrap().unwrap();
+    let param = Infos::get_query_param("row", &Some(row));
+    let param_type = param.parameter_type.unwrap();
+    assert_eq!(param_type.type_.as_deref(), Some("STRUCT"));
+    let struct_types: Vec<(String, String)> = param_type.struct_types.unwrap().into_iter()
+        .map(|t| (t.name.unwrap(), t.type_.unwrap().type_.unwrap()))
+        .collect();
+    assert_eq!(struct_types[0], ("Id".to_string(), "INT64".to_string()));
+    assert!(struct_types.contains(&("info4i".to_string(), "INT64".to_string())));
+    assert!(struct_types.contains(&("yes".to_string(), "BOOL".to_string())));
+    assert!(!struct_types.iter().any(|(name, _)| name == "client" || name == "snapshot"));
+    let struct_values = param.parameter_value.unwrap().struct_values.unwrap();
+    assert_eq!(struct_values.len(), struct_types.len());
+    assert_eq!(struct_values["Id"].value.as_deref(), Some("19"));
+    assert_eq!(struct_values["row_ids"].value.as_deref(), Some("19"));
+    assert_eq!(struct_values["info1"].value, None);
+
+    let rows = Infos::load_many_from_pk(&client, vec!["3".to_string(), "19".to_string()]).await.unwrap();
+    let param = Infos::get_query_param("rows", &Some(rows));
+    let element_type = param.parameter_type.unwrap().array_type.flatten().unwrap();
+    assert_eq!(element_type.type_.as_deref(), Some("STRUCT"));
+    let elements = param.parameter_value.unwrap().array_values.unwrap();
+    assert_eq!(elements.len(), 2);
+    assert!(elements.iter().all(|e| e.struct_values.as_ref().unwrap().contains_key("row_ids")));
+    assert!(Infos::from_bigquery_value(&"{}".to_string()).is_err());
+}
+
+#[tokio::test]
+async fn untyped_query() {
+    let (_fake, client) = get_fake_client().await;
+    let query = format!("select row_ids, Id, info1, yes from {} where Id > @min order by Id",
+                        Infos::get_identifier_from_client(&client).await.unwrap());
+    let result = client.query(&query, vec![google_bigquery::utils::get_query_param("min", &Some(5i64))]).await.unwrap();
+    assert_eq!(result.column_names(), vec!["row_ids", "Id", "info1", "yes"]);
+    assert_eq!(result.len(), 3);
+    let row = &result.rows()[0];
+    assert_eq!(row.get::<i64>("Id").unwrap(), 7);
+    assert_eq!(row.get_at::<String>(0).unwrap(), "7");
+    assert_eq!(row["row_ids"].as_deref(), Some("7"));
+    assert_eq!(row[1].as_deref(), Some("7"));
+    assert_eq!(row.get_opt::<String>("info1").unwrap(), None);
+    assert!(matches!(row.get::<String>("info1"), Err(Error::Parse { value: None, .. })));
+    assert!(matches!(row.get::<i64>("nope"), Err(Error::Parse { .. })));
+    assert!(matches!(row.get_at::<i64>(4), Err(Error::Parse { .. })));
+    let ids: Vec<i64> = result.into_iter().map(|row| row.get("Id").unwrap()).collect();
+    assert_eq!(ids, vec![7, 19, 123123]);
+
+    let result = client.query("select count(*) as amount", vec![]).await.unwrap();
+    assert_eq!(result.rows()[0].get::<i64>("amount").unwrap(), 1);
+}
+
+#[tokio::test]
+async fn deserialize_rows() {
+    #[derive(Debug, serde::Deserialize)]
+    #[allow(dead_code)]
+    struct Summary {
+        #[serde(rename = "Id")]
+        id: u32,
+        row_ids: String,
+        info1: Option<String>,
+        yes: Option<bool>,
+    }
+
+    let (_fake, client) = get_fake_client().await;
+    let query = format!("select row_ids, Id, info1, yes, info3 from {} where Id < 10 order by Id",
+                        Infos::get_identifier_from_client(&client).await.unwrap());
+    let result = client.query(&query, vec![]).await.unwrap();
+    let rows: Vec<Summary> = result.deserialize_rows().unwrap();
+    assert_eq!(rows.len(), 3);
+    assert_eq!((rows[0].id, rows[0].row_ids.as_str(), rows[0].info1.as_deref()), (1, "1", None));
+    assert_eq!(rows.iter().map(|r| r.id).collect::<Vec<_>>(), vec![1, 3, 7]);
+
+    let tuples: Vec<(String, i64)> = result.deserialize_rows().unwrap();
+    assert_eq!(tuples[2], ("7".to_string(), 7));
+    let values: Vec<serde_json::Value> = result.deserialize_rows().unwrap();
+    assert_eq!(values[2]["Id"], serde_json::json!(7));
+    assert_eq!(values[2]["info1"], serde_json::Value::Null);
+
+    #[derive(Debug, serde::Deserialize)]
+    struct Required {
+        #[allow(dead_code)]
+        info1: String,
+    }
+    let error = result.deserialize_rows::<Required>().unwrap_err();
+    assert!(matches!(error, Error::Parse { ref struct_name, ref field, value: None, .. } if struct_name == "Required" && field == "info1"), "{}", error);
+
+    #[derive(Debug, serde::Deserialize)]
+    struct Missing {
+        #[allow(dead_code)]
+        nope: String,
+    }
+    let error = result.deserialize_rows::<Missing>().unwrap_err();
+    assert!(matches!(error, Error::Parse { ref struct_name, ref field, value: None, .. } if struct_name == "Missing" && field == "nope"), "{}", error);
+    let row = &result.rows()[0];
+    let id: (u32,) = google_bigquery::from_table_row(row.table_row(), result.schema()).unwrap();
+    assert_eq!(id.0, 1);
+}
+
+#[tokio::test]
+async fn load_only_some_columns() {
+    let (fake, client) = get_fake_client().await;
+    let table = "testrustproject-372221.test1.Infos";
+    let mut rows = Infos::load_by_field_with_columns(&client, stringify!(info3), Some("cc".to_string()), &[stringify!(yes)], 10).await.unwrap();
+    let query = fake.get_requests().pop().unwrap().query.unwrap();
+    assert!(query.starts_with("select row_ids, yes from"), "{}", query);
+    assert_eq!(rows.len(), 2);
+    let row = rows.iter_mut().find(|r| r.row_ids == "19").unwrap();
+    assert_eq!(row.yes, Some(true));
+    assert_eq!(row.info3, None);
+    assert_eq!(row.row_id, -9999);
+    assert_eq!(row.get_loaded_fields(), Some(vec!["row_ids".to_string(), "yes".to_string()]));
+
+    // only the changed field is written, the ones that were not loaded keep their values
+    row.yes = Some(false);
+    row.save_to_bigquery().await.unwrap();
+    let stored = fake.get_rows(table).into_iter().find(|r| r["row_ids"].as_deref() == Some("19")).unwrap();
+    assert_eq!(stored["yes"].as_deref(), Some("false"));
+    assert_eq!(stored["info3"].as_deref(), Some("cc"));
+    assert_eq!(stored["Id"].as_deref(), Some("19"));
+
+    let query = format!("select row_ids, info1 from {} where info1 is not null", Infos::get_identifier_from_client(&client).await.unwrap());
+    let rows = Infos::load_by_custom_query_with_columns(&client, &query, vec![], &[stringify!(info1)], 10).await.unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].info1.as_deref(), Some("a"));
+    assert_eq!(Infos::load_from_pk(&client, "3".to_string()).await.unwrap().unwrap().get_loaded_fields(), None);
+
+    assert!(matches!(Infos::load_by_field_with_columns(&client, stringify!(yes), Some(true), &["nope"], 10).await,
+        Err(Error::UnknownField { .. })));
+}
+
+#[tokio::test]
+async fn projected_rows_keep_unloaded_columns() {
+    let (fake, client) = get_fake_client().await;
+    let table = "testrustproject-372221.test1.Infos";
+    let stored = |fake: &FakeBigquery| fake.get_rows(table).into_iter().find(|r| r["row_ids"].as_deref() == Some("19")).unwrap();
+    let mut rows = Infos::load_by_field_with_columns(&client, stringify!(row_ids), Some("19".to_string()), &[stringify!(yes)], 1).await.unwrap();
+    let mut row = rows.pop().unwrap();
+
+    // info3 was not loaded, assigning its default value can not be told apart from not touching it
+    row.info3 = None;
+    assert_eq!(row.update_in_bigquery().await.unwrap(), 0);
+    assert_eq!(stored(&fake)["info3"].as_deref(), Some("cc"));
+    // unless it is marked as changed
+    row.snapshot.mark_changed("info3");
+    assert_eq!(row.update_in_bigquery().await.unwrap(), 1);
+    assert_eq!(stored(&fake)["info3"].as_deref(), None);
+    let query = fake.get_requests().pop().unwrap().query.unwrap();
+    assert!(query.contains("set info3 = @__info3 where"), "{}", query);
+    // the mark is gone after the save
+    assert_eq!(row.update_in_bigquery().await.unwrap(), 0);
+
+    // changed values of fields that were not loaded are written
+    row.int_info4 = Some(5);
+    assert_eq!(row.update_in_bigquery().await.unwrap(), 1);
+    assert_eq!(stored(&fake)["info4i"].as_deref(), Some("5"));
+    assert_eq!(stored(&fake)["Id"].as_deref(), Some("19"));
+
+    let full = Infos::load_from_pk(&client, "3".to_string()).await.unwrap().unwrap();
+    assert!(matches!(Infos::save_many_to_bigquery(&client, &[full, row]).await, Err(Error::Other(_))));
+    assert_eq!(stored(&fake)["Id"].as_deref(), Some("19"));
+}
+
+#[tokio::test]
+async fn projected_rows_need_a_snapshot() {
+    let (fake, client) = get_fake_client().await;
+    let table = "testrustproject-372221.test1.Versioned";
+    fake.create_table(table, &[("id", "STRING"), ("version", "INT64"), ("value", "STRING")]);
+    fake.insert_row(table, &[("id", Some("a")), ("version", Some("1")), ("value", Some("kept"))]);
+    let query = format!("select id, version from {}", Versioned::get_identifier_from_client(&client).await.unwrap());
+    let response = client.run_query(QueryRequest { query: Some(query), ..Default::default() }, "testrustproject-372221").await.unwrap();
+    let row = &response.rows.unwrap()[0];
+    let mapping = std::collections::HashMap::from([("id".to_string(), 0), ("version".to_string(), 1)]);
+
+    // a struct without a snapshot can not record the loaded fields, so it only accepts whole rows
+    let mut versioned = Versioned::default();
+    let columns = ["id".to_string(), "version".to_string()];
+    assert!(matches!(versioned.write_columns_from_table_row(row, &mapping, Some(&columns)), Err(Error::Other(_))));
+    assert!(matches!(versioned.write_from_table_row(row, &mapping), Err(Error::Parse { ref field, .. }) if field == "value"));
+}
+
+#[tokio::test]
+async fn custom_queries_respect_max_amount() {
+    let (fake, client) = get_fake_client().await;
+    fake.set_page_size(Some(2));
+    let query = format!("select * from {} order by Id", Infos::get_identifier_from_client(&client).await.unwrap());
+    let rows = Infos::load_by_custom_query(&client, &query, vec![], 3).await.unwrap();
+    assert_eq!(rows.iter().map(|r| r.row_id).collect::<Vec<i64>>(), vec![1, 3, 7]);
+    // the last page is not read
+    assert_eq!(fake.get_results_requests().len(), 1);
+
+    let columns_query = format!("select row_ids, info3 from {} order by Id", Infos::get_identifier_from_client(&client).await.unwrap());
+    let rows = Infos::load_by_custom_query_with_columns(&client, &columns_query, vec![], &[stringify!(info3)], 1).await.unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].row_ids, "1");
+    assert_eq!(Infos::load_by_custom_query_lenient(&client, &query, vec![], 4).await.unwrap().rows.len(), 4);
+    assert_eq!(Infos::load_by_custom_query_lenient(&client, &query, vec![], 10).await.unwrap().rows.len(), 5);
+}
+
+#[tokio::test]
+async fn fake_from_pk() {
+    let (_, client) = get_fake_client().await;
+    let i1 = Infos::load_from_pk(&client, "3".to_string()).await.unwrap().unwrap();
+    assert_eq!(i1.row_id, 3);
+    assert_eq!(i1.info1, Some("a".to_string()));
+    assert_eq!(i1.info3, Some("c".to_string()));
+    assert_eq!(i1.int_info4, None);
+    assert_eq!(i1.yes, None);
+
+    assert!(Infos::load_from_pk(&client, "1234".to_string()).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn fake_unknown_table() {
+    let client = BigqueryClient::builder()
+        .project_id("testrustproject-372221")
+        .dataset_id("test1")
+        .executor(FakeBigquery::new())
+        .build().await.unwrap();
+    assert!(Infos::load_from_pk(&client, "3".to_string()).await.is_err());
+}
+
+#[tokio::test]
+async fn record_and_replay_fixtures() {
+    let path = std::env::temp_dir().join(format!("google_bigquery_fixtures_{}.json", std::process::id()));
+    let (fake, _) = get_fake_client().await;
+    let client = BigqueryClient::builder()
+        .project_id("testrustproject-372221")
+        .dataset_id("test1")
+        .executor(fake.clone())
+        .record_to(&path)
+        .build().await.unwrap();
+    let mut i1 = Infos::load_from_pk(&client, "3".to_string()).await.unwrap().unwrap();
+    i1.info2 = Some("b".to_string());
+    i1.save_to_bigquery().await.unwrap();
+    i1.load_from_bigquery().await.unwrap();
+    assert!(Infos::load_from_pk(&client, "1234".to_string()).await.unwrap().is_none());
+    fake.set_page_size(Some(2));
+    assert_eq!(Infos::load_by_field(&client, stringify!(yes), Some(true), 10).await.unwrap().len(), 3);
+    let recorded = fake.get_requests().len();
+
+    let client = BigqueryClient::builder()
+        .project_id("testrustproject-372221")
+        .dataset_id("test1")
+        .replay_from(&path)
+        .build().await.unwrap();
+    let mut i1 = Infos::load_from_pk(&client, "3".to_string()).await.unwrap().unwrap();
+    assert_eq!(i1.info2, None);
+    i1.info2 = Some("b".to_string());
+    i1.save_to_bigquery().await.unwrap();
+    i1.info2 = None;
+    i1.load_from_bigquery().await.unwrap();
+    assert_eq!(i1.info2, Some("b".to_string()));
+    assert!(Infos::load_from_pk(&client, "1234".to_string()).await.unwrap().is_none());
+    assert_eq!(Infos::load_by_field(&client, stringify!(yes), Some(true), 10).await.unwrap().len(), 3);
+    assert!(Infos::load_by_field(&client, stringify!(info1), Some("never recorded".to_string()), 10).await.is_err());
+    assert_eq!(fake.get_requests().len(), recorded);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn error_variants() {
+    let (fake, client) = get_fake_client().await;
+
+    let e = Infos::load_by_field(&client, "not_a_field", Some(1), 10).await.unwrap_err();
+    assert!(matches!(e, Error::UnknownField { ref field, .. } if field == "not_a_field"), "{:?}", e);
+
+    let mut i1 = Infos::create_with_pk(&client, "1234".to_string());
+    let e = i1.load_from_bigquery().await.unwrap_err();
+    assert!(matches!(e, Error::NotFound(_)), "{:?}", e);
+
+    fake.insert_row("testrustproject-372221.test1.Infos", &[("Id", Some("3")), ("row_ids", Some("3"))]);
+    let mut i3 = Infos::create_with_pk(&client, "3".to_string());
+    let e = i3.save_to_bigquery().await.unwrap_err();
+    assert!(matches!(e, Error::DuplicatePrimaryKey { ref primary_key, .. } if primary_key == "\"3\""), "{:?}", e);
+
+    let client = BigqueryClient::builder()
+        .project_id("testrustproject-372221")
+        .dataset_id("test1")
+        .executor(FakeBigquery::new())
+        .build().await.unwrap();
+    let e = Infos::load_from_pk(&client, "3".to_string()).await.unwrap_err();
+    assert!(matches!(e, Error::Http { status: 404, reason: Some(ref reason), .. } if reason == "notFound"), "{:?}", e);
+}
+
+#[tokio::test]
+async fn parse_error_names_field_and_value() {
+    let executor = FnExecutor {
+        requests: Default::default(),
+        answer: |_: &QueryRequest| Ok(serde_json::from_str(r#"{
+        "schema": {"fields": [{"name": "Id", "type": "INTEGER"}, {"name": "row_ids", "type": "STRING"}, {"name": "info1", "type": "STRING"},
+            {"name": "info", "type": "STRING"}, {"name": "info3", "type": "STRING"}, {"name": "info4i", "type": "INTEGER"}, {"name": "yes", "type": "BOOLEAN"}]},
+        "rows": [{"f": [{"v": "not a number"}, {"v": "3"}, {"v": null}, {"v": null}, {"v": null}, {"v": null}, {"v": null}]}],
+        "jobComplete": true
+    }"#).unwrap()),
+    };
+    let client = BigqueryClient::builder()
+        .project_id("testrustproject-372221")
+        .dataset_id("test1")
+        .executor(executor)
+        .build().await.unwrap();
+    let e = Infos::load_from_pk(&client, "3".to_string()).await.unwrap_err();
+    match e {
+        Error::Parse { struct_name, field, value, .. } => {
+            assert_eq!(struct_name, "Infos");
+            assert_eq!(field, "Id");
+            assert_eq!(value.as_deref(), Some("not a number"));
+        }
+        e => panic!("unexpected error: {:?}", e),
+    }
+}
+
+#[tokio::test]
+async fn decode_errors_do_not_panic() {
+    let (fake, client) = get_fake_client().await;
+    let e = Infos::load_by_custom_query(&client, "select Id, row_ids from test1.Infos", vec![], 10).await.unwrap_err();
+    assert!(matches!(e, Error::Parse { ref field, value: None, .. } if field == "info1"), "{:?}", e);
+
+    fake.insert_row("testrustproject-372221.test1.Infos", &[("row_ids", Some("no id"))]);
+    let e = Infos::load_from_pk(&client, "no id".to_string()).await.unwrap_err();
+    assert!(matches!(e, Error::Parse { ref struct_name, ref field, value: None, .. } if struct_name == "Infos" && field == "Id"), "{:?}", e);
+}
+
+#[tokio::test]
+async fn lenient_load_collects_errors() {
+    let (fake, client) = get_fake_client().await;
+    fake.insert_row("testrustproject-372221.test1.Infos", &[("row_ids", Some("no id")), ("yes", Some("true"))]);
+
+    assert!(Infos::load_by_field(&client, stringify!(yes), Some(true), 10).await.is_err());
+
+    let loaded = Infos::load_by_field_lenient(&client, stringify!(yes), Some(true), 10).await.unwrap();
+    let mut ids: Vec<i64> = loaded.rows.iter().map(|i| i.row_id).collect();
+    ids.sort();
+    assert_eq!(ids, vec![1, 19, 123123]);
+    assert_eq!(loaded.errors.len(), 1);
+    assert!(matches!(loaded.errors[0], Error::Parse { ref field, .. } if field == "Id"), "{:?}", loaded.errors[0]);
+}
+
+#[test]
+fn error_from_error_response() {
+    let e: Error = google_bigquery2::Error::BadRequest(serde_json::json!({
+        "error": {
+            "code": 400,
+            "message": "Unrecognized name: infos at [1:8]",
+            "errors": [{"reason": "invalidQuery", "message": "Unrecognized name: infos at [1:8]", "location": "q"}],
+        }
+    })).into();
+    match e {
+        Error::Http { status, reason, message, location } => {
+            assert_eq!(status, 400);
+            assert_eq!(reason.as_deref(), Some("invalidQuery"));
+            assert_eq!(message, "Unrecognized name: infos at [1:8]");
+            assert_eq!(location.as_deref(), Some("q"));
+        }
+        e => panic!("unexpected error: {:?}", e),
+    }
+}
+
+#[tokio::test]
+async fn read_all_pages() {
+    let (fake, client) = get_fake_client().await;
+    fake.set_page_size(Some(2));
+    let mut q = Infos::load_by_field(&client, stringify!(yes), Some(true), 10).await.unwrap();
+    q.sort_by_key(|i| i.row_id);
+    assert_eq!(q.iter().map(|i| i.row_id).collect::<Vec<i64>>(), vec![1, 19, 123123]);
+
+    let results_requests = fake.get_results_requests();
+    assert_eq!(results_requests.len(), 1);
+    assert_eq!(results_requests[0].page_token.as_deref(), Some("2"));
+    assert_eq!(results_requests[0].job_id, "fake_job_1");
+}
+
+#[tokio::test]
+async fn stream_pages_lazily() {
+    let (fake, client) = get_fake_client().await;
+    fake.set_page_size(Some(2));
+    let mut stream = Infos::stream_by_field(&client, stringify!(yes), Some(true));
+    let first = stream.next().await.unwrap().unwrap();
+    assert!(first.yes.unwrap());
+    assert_eq!(fake.get_results_requests().len(), 0);
+
+    let mut ids = vec![first.row_id];
+    while let Some(info) = stream.next().await {
+        ids.push(info.unwrap().row_id);
+    }
+    ids.sort();
+    assert_eq!(ids, vec![1, 19, 123123]);
+    assert_eq!(fake.get_results_requests().len(), 1);
+    assert!(!fake.get_requests()[0].query.as_ref().unwrap().contains("limit"));
+}
+
+#[tokio::test]
+async fn stream_keeps_going_after_decode_errors() {
+    let (fake, client) = get_fake_client().await;
+    fake.set_page_size(Some(1));
+    fake.insert_row("testrustproject-372221.test1.Infos", &[("row_ids", Some("no id")), ("yes", Some("true"))]);
+
+    let query = format!("select {} from {} where yes = true", Infos::get_query_fields_str(), Infos::get_identifier_from_client(&client).await.unwrap());
+    let rows: Vec<Result<Infos, Error>> = Infos::stream_by_custom_query(&client, &query, vec![]).collect().await;
+    assert_eq!(rows.len(), 4, "{:?}", rows);
+    assert_eq!(rows.iter().filter(|row| row.is_err()).count(), 1);
+    assert_eq!(fake.get_results_requests().len(), 3);
+}
+
+#[tokio::test]
+async fn wait_for_job_completion() {
+    let (fake, client) = get_fake_client().await;
+    fake.set_incomplete_responses(3);
+    let i1 = Infos::load_from_pk(&client, "3".to_string()).await.unwrap().unwrap();
+    assert_eq!(i1.info1, Some("a".to_string()));
+    assert_eq!(fake.get_results_requests().len(), 3);
+}
+
+#[tokio::test]
+async fn wait_timeout() {
+    let (fake, _) = get_fake_client().await;
+    fake.set_incomplete_responses(usize::MAX);
+    let client = BigqueryClient::builder()
+        .project_id("testrustproject-372221")
+        .dataset_id("test1")
+        .executor(fake.clone())
+        .wait_timeout(std::time::Duration::from_millis(50))
+        .build().await.unwrap();
+    let e = Infos::load_from_pk(&client, "3".to_string()).await.unwrap_err();
+    assert!(matches!(e, Error::JobTimeout { ref job_id, .. } if job_id == "fake_job_1"), "{:?}", e);
+}
+
+/// A client backed by a fake that holds a copy of the `test1.Infos` test data.
+async fn get_fake_client() -> (FakeBigquery, BigqueryClient) {
+    let fake = FakeBigquery::new();
+    let table = "testrustproject-372221.test1.Infos";
+    fake.create_table(table, &[("Id", "INT64"), ("row_ids", "STRING"), ("info1", "STRING"), ("info", "STRING"),
+        ("info3", "STRING"), ("info4i", "INT64"), ("yes", "BOOL")]);
+    fake.insert_row(table, &[("Id", Some("3")), ("row_ids", Some("3")), ("info1", Some("a")), ("info3", Some("c"))]);
+    fake.insert_row(table, &[("Id", Some("1")), ("row_ids", Some("1")), ("info3", Some("x3")), ("yes", Some("true"))]);
+    fake.insert_row(table, &[("Id", Some("19")), ("row_ids", Some("19")), ("info3", Some("cc")), ("yes", Some("true"))]);
+    fake.insert_row(table, &[("Id", Some("123123")), ("row_ids", Some("123123")), ("info3", Some("cc")), ("yes", Some("true"))]);
+    fake.insert_row(table, &[("Id", Some("7")), ("row_ids", Some("7")), ("info4i", Some("7")), ("yes", Some("false"))]);
+
+    let client = BigqueryClient::builder()
+        .project_id("testrustproject-372221")
+        .dataset_id("test1")
+        .executor(fake.clone())
+        .build().await.unwrap();
+    (fake, client)
+}
+
+//endregion
+
+#[tokio::test]
+async fn missing_service_account_file_is_an_error() {
+    let client = BigqueryClient::new("testrustproject-372221", "test1",
+                                     Credentials::ServiceAccountFile("auth/does_not_exist.json".to_string())).await;
+    assert!(client.is_err());
+}
+
+#[tokio::test]
+async fn static_token_credentials() {
+    let client = BigqueryClient::new("testrustproject-372221", "test1",
+                                     Credentials::StaticToken("token".to_string())).await.unwrap();
+    assert_eq!(client.get_project_id(), "testrustproject-372221");
+}
+
+#[test]
+fn credentials_debug_hides_secrets() {
+    let token = format!("{:?}", Credentials::StaticToken("secret-token".to_string()));
+    assert!(!token.contains("secret-token"), "{}", token);
+    let key = format!("{:?}", Credentials::ServiceAccountKey(r#"{"private_key": "secret-key"}"#.to_string()));
+    assert!(!key.contains("secret-key"), "{}", key);
+    assert!(key.starts_with("ServiceAccountKey"));
+    let file = format!("{:?}", Credentials::ServiceAccountFile("auth/key.json".to_string()));
+    assert!(file.contains("auth/key.json"));
+}
+
+#[tokio::test]
+async fn root_url_override() {
+    let response = r#"{
+        "jobComplete": true,
+        "schema": {"fields": [
+            {"name": "Id", "type": "INTEGER"}, {"name": "row_ids", "type": "STRING"},
+            {"name": "info1", "type": "STRING"}, {"name": "info", "type": "STRING"},
+            {"name": "info3", "type": "STRING"}, {"name": "info4i", "type": "INTEGER"},
+            {"name": "yes", "type": "BOOLEAN"}
+        ]},
+        "rows": [{"f": [{"v": "3"}, {"v": "3"}, {"v": "a"}, {"v": null}, {"v": "c"}, {"v": null}, {"v": null}]}]
+    }"#;
+    let (root_url, request) = serve_once(response).await;
+    let client = BigqueryClient::new_emulator("testrustproject-372221".to_string(), "test1".to_string(), root_url).await.unwrap();
+
+    let q = Infos::load_by_field(&client, stringify!(info1), Some("a".to_string()), 10).await.unwrap();
+    assert_eq!(q.len(), 1);
+    assert_eq!(q[0].row_id, 3);
+    assert_eq!(q[0].info3, Some("c".to_string()));
+
+    let request = request.await.unwrap();
+    assert!(request.starts_with("POST /bigquery/v2/projects/testrustproject-372221/queries"));
+}
+
+#[tokio::test]
+async fn builder_query_options() {
+    let response = r#"{"jobComplete": true, "schema": {"fields": []}}"#;
+    let (root_url, request) = serve_once(response).await;
+    let client = BigqueryClient::builder()
+        .project_id("testrustproject-372221")
+        .dataset_id("test1")
+        .credentials(Credentials::Unauthenticated)
+        .root_url(root_url)
+        .user_agent("my-agent/1.0")
+        .request_timeout(std::time::Duration::from_secs(10))
+        .location("EU")
+        .label("team", "data")
+        .use_query_cache(false)
+        .build().await.unwrap();
+
+    let q = Infos::load_by_field(&client, stringify!(info1), Some("a".to_string()), 10).await.unwrap();
+    assert_eq!(q.len(), 0);
+
+    let request = request.await.unwrap().to_lowercase();
+    assert!(request.contains("user-agent: my-agent/1.0"));
+    assert!(request.contains(r#""location":"eu""#));
+    assert!(request.contains(r#""labels":{"team":"data"}"#));
+    assert!(request.contains(r#""usequerycache":false"#));
+}
+
+#[tokio::test]
+async fn builder_requires_project_and_dataset() {
+    let client = BigqueryClient::builder()
+        .dataset_id("test1")
+        .credentials(Credentials::Unauthenticated)
+        .build().await;
+    assert!(client.is_err());
+}
+
+#[tokio::test]
+async fn retry_transient_errors() {
+    let rate_limited = r#"{"error": {"code": 403, "message": "Exceeded rate limits",
+        "errors": [{"reason": "rateLimitExceeded", "message": "Exceeded rate limits"}]}}"#;
+    let concurrent_update = r#"{"error": {"code": 400, "message": "Could not serialize access to table test1.Infos due to concurrent update",
+        "errors": [{"reason": "invalidQuery", "message": "Could not serialize access to table test1.Infos due to concurrent update"}]}}"#;
+    let ok = r#"{"jobComplete": true, "schema": {"fields": []}}"#;
+    let (root_url, requests) = serve(vec![(403, rate_limited), (400, concurrent_update), (200, ok)]).await;
+    let client = get_retrying_client(root_url, 3).await;
+
+    let q = Infos::load_by_field(&client, stringify!(info1), Some("a".to_string()), 10).await.unwrap();
+    assert_eq!(q.len(), 0);
+    assert_eq!(requests.await.unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn retry_gives_up() {
+    let backend_error = r#"{"error": {"code": 500, "message": "Backend error", "errors": [{"reason": "backendError"}]}}"#;
+    let invalid = r#"{"error": {"code": 400, "message": "Syntax error", "errors": [{"reason": "invalidQuery"}]}}"#;
+
+    let (root_url, requests) = serve(vec![(500, backend_error), (500, backend_error)]).await;
+    let client = get_retrying_client(root_url, 2).await;
+    assert!(Infos::load_by_field(&client, stringify!(info1), Some("a".to_string()), 10).await.is_err());
+    assert_eq!(requests.await.unwrap().len(), 2);
+
+    let (root_url, requests) = serve(vec![(400, invalid)]).await;
+    let client = get_retrying_client(root_url, 5).await;
+    assert!(Infos::load_by_field(&client, stringify!(info1), Some("a".to_string()), 10).await.is_err());
+    assert_eq!(requests.await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn custom_executor() {
+    let requests = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+    let executor = FnExecutor {
+        requests: requests.clone(),
+        answer: |req: &QueryRequest| {
+            let query = req.query.as_ref().unwrap();
+            let response = if query.starts_with("select count(*)") {
+                r#"{"jobComplete": true, "schema": {"fields": [{"name": "f0_", "type": "INTEGER"}]}, "rows": [{"f": [{"v": "1"}]}]}"#
+            } else {
+                r#"{"jobComplete": true, "numDmlAffectedRows": "1"}"#
+            };
+            Ok(serde_json::from_str(response).unwrap())
+        },
+    };
+    let client = BigqueryClient::builder()
+        .project_id("testrustproject-372221")
+        .dataset_id("test1")
+        .executor(executor)
+        .build().await.unwrap();
+
+    let mut i1 = Infos::create_with_pk(&client, "3".to_string());
+    i1.info1 = Some("a".to_string());
+    i1.save_to_bigquery().await.unwrap();
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].query.as_deref(), Some("select count(*) from test1.Infos where row_ids = @__row_ids limit 1"));
+    assert!(requests[1].query.as_ref().unwrap().starts_with("update test1.Infos set "));
+    let params = requests[1].query_parameters.as_ref().unwrap();
+    let info1 = params.iter().find(|p| p.name.as_deref() == Some("__info1")).unwrap();
+    assert_eq!(info1.parameter_value.as_ref().unwrap().value.as_deref(), Some("a"));
+}
+
+/// Answers every query with the result of `answer` and remembers the requests.
+struct FnExecutor<F> {
+    requests: std::sync::Arc<std::sync::Mutex<Vec<QueryRequest>>>,
+    answer: F,
+}
+
+#[async_trait::async_trait]
+impl<F> QueryExecutor for FnExecutor<F>
+    where F: Fn(&QueryRequest) -> Result<QueryResponse, Error> + Send + Sync {
+    async fn query(&self, req: QueryRequest, _project_id: &str) -> Result<QueryResponse, Error> {
+        let response = (self.answer)(&req);
+        self.requests.lock().unwrap().push(req);
+        response
+    }
+}
+
+#[test]
+fn retry_backoff() {
+    let policy = RetryPolicy {
+        initial_backoff: std::time::Duration::from_secs(1),
+        max_backoff: std::time::Duration::from_secs(5),
+        jitter: 0.0,
+        ..Default::default()
+    };
+    assert_eq!(policy.get_backoff(1).as_secs(), 1);
+    assert_eq!(policy.get_backoff(2).as_secs(), 2);
+    assert_eq!(policy.get_backoff(3).as_secs(), 4);
+    assert_eq!(policy.get_backoff(4).as_secs(), 5);
+
+    let policy = RetryPolicy { jitter: 0.5, ..policy };
+    let backoff = policy.get_backoff(2);
+    assert!(backoff <= std::time::Duration::from_secs(2) && backoff >= std::time::Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn retried_writes_are_not_applied_twice() {
+    let (fake, _) = get_fake_client().await;
+    let table = "testrustproject-372221.test1.Infos";
+    let client = BigqueryClient::builder()
+        .project_id("testrustproject-372221")
+        .dataset_id("test1")
+        .executor(fake.clone())
+        .retry_policy(RetryPolicy {
+            initial_backoff: std::time::Duration::from_millis(1),
+            ..Default::default()
+        })
+        .build().await.unwrap();
+
+    fake.set_lost_responses(1);
+    let mut row = Infos::create_with_pk(&client, "42".to_string());
+    row.row_id = 42;
+    assert_eq!(row.insert_to_bigquery().await.unwrap(), 1);
+    let requests = fake.get_requests();
+    let (first, retry) = (&requests[requests.len() - 2], &requests[requests.len() - 1]);
+    assert!(first.request_id.is_some());
+    assert_eq!(first.request_id, retry.request_id);
+    assert_eq!(fake.get_rows(table).iter().filter(|r| r["row_ids"].as_deref() == Some("42")).count(), 1);
+
+    fake.set_lost_responses(1);
+    assert_eq!(Infos::delete_by_field(&client, stringify!(info3), Some("cc".to_string())).await.unwrap(), 2);
+    assert_eq!(fake.get_rows(table).len(), 4);
+    // every statement gets its own request id
+    let requests = fake.get_requests();
+    assert_ne!(requests[requests.len() - 1].request_id, first.request_id);
+}
+
+#[tokio::test]
+async fn retried_conflicts_get_a_new_request_id() {
+    let (fake, _) = get_fake_client().await;
+    let table = "testrustproject-372221.test1.Infos";
+    let client = BigqueryClient::builder()
+        .project_id("testrustproject-372221")
+        .dataset_id("test1")
+        .executor(fake.clone())
+        .retry_policy(RetryPolicy {
+            initial_backoff: std::time::Duration::from_millis(1),
+            ..Default::default()
+        })
+        .build().await.unwrap();
+
+    // a failed job is answered again for its request id, like BigQuery does
+    fake.set_concurrent_update_errors(1);
+    let req = QueryRequest {
+        query: Some(format!("delete from `{}` where Id = 7", table)),
+        request_id: Some("same-id".to_string()),
+        ..Default::default()
+    };
+    for _ in 0..2 {
+        let e = fake.query(req.clone(), "testrustproject-372221").await.unwrap_err();
+        assert!(e.to_string().contains("Could not serialize access to table"), "{}", e);
+    }
+
+    fake.set_concurrent_update_errors(1);
+    let mut row = Infos::create_with_pk(&client, "42".to_string());
+    row.row_id = 42;
+    assert_eq!(row.insert_to_bigquery().await.unwrap(), 1);
+    let requests = fake.get_requests();
+    let (first, retry) = (&requests[requests.len() - 2], &requests[requests.len() - 1]);
+    assert_eq!(first.query, retry.query);
+    assert!(first.request_id.is_some() && retry.request_id.is_some());
+    assert_ne!(first.request_id, retry.request_id);
+    assert_eq!(fake.get_rows(table).iter().filter(|r| r["row_ids"].as_deref() == Some("42")).count(), 1);
+}
+
+async fn get_retrying_client(root_url: String, max_attempts: u32) -> BigqueryClient {
+    BigqueryClient::builder()
+        .project_id("testrustproject-372221")
+        .dataset_id("test1")
+        .credentials(Credentials::Unauthenticated)
+        .root_url(root_url)
+        .retry_policy(RetryPolicy {
+            max_attempts,
+            initial_backoff: std::time::Duration::from_millis(1),
+            ..Default::default()
+        })
+        .build().await.unwrap()
+}
+
+/// Answers the first http request on a local port with `body` and returns
+/// the root url to reach it and the received request.
+async fn serve_once(body: &'static str) -> (String, tokio::task::JoinHandle<String>) {
+    let (root_url, handle) = serve(vec![(200, body)]).await;
+    (root_url, tokio::spawn(async move { handle.await.unwrap().remove(0) }))
+}
+
+/// Answers one http request per entry of `responses` (status, body) on a local port
+/// and returns the root url to reach it and the received requests.
+async fn serve(responses: Vec<(u16, &'static str)>) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let root_url = format!("http://{}", listener.local_addr().unwrap());
+    let handle = tokio::spawn(async move {
+        let mut requests = vec![];
+        for (status, body) in responses {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = vec![0u8; 64 * 1024];
+            loop {
+                let n = socket.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..n]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some(header_end) = text.find("\r\n\r\n") {
+                    let content_length = text[..header_end].lines()
+                        .find_map(|l| l.to_lowercase().strip_prefix("content-length: ").map(|v| v.trim().parse::<usize>().unwrap()))
+                        .unwrap_or(0);
+                    if request.len() >= header_end + 4 + content_length || n == 0 {
+                        break;
+                    }
+                }
+            }
+            let response = format!("HTTP/1.1 {} X\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                                   status, body.len(), body);
+            socket.write_all(response.as_bytes()).await.unwrap();
+            requests.push(String::from_utf8_lossy(&request).to_string());
+        }
+        requests
+    });
+    (root_url, handle)
+}
+
+/// Uses the emulator at `BIGQUERY_EMULATOR_HOST` (e.g. `http://localhost:9050`) if set,
+/// the real test project otherwise.
+///
+/// With `BIGQUERY_RECORD_FIXTURES=<dir>` the exchanges of each test are recorded to `<dir>/<test name>.json`,
+/// with `BIGQUERY_REPLAY_FIXTURES=<dir>` they are replayed from there without any network access.
 async fn get_test_client() -> BigqueryClient {
-    let client = BigqueryClient::new("testrustproject-372221", "test1", None).await.unwrap();
-    client
+    let fixture_file = || {
+        let test_name = std::thread::current().name().unwrap_or("unknown").replace("::", "_");
+        format!("{}.json", test_name)
+    };
+    let mut builder = BigqueryClient::builder()
+        .project_id("testrustproject-372221")
+        .dataset_id("test1");
+    if let Ok(dir) = std::env::var("BIGQUERY_REPLAY_FIXTURES") {
+        return builder.replay_from(std::path::Path::new(&dir).join(fixture_file())).build().await.unwrap();
+    }
+    if let Ok(dir) = std::env::var("BIGQUERY_RECORD_FIXTURES") {
+        builder = builder.record_to(std::path::Path::new(&dir).join(fixture_file()));
+    }
+    if let Ok(emulator_host) = std::env::var("BIGQUERY_EMULATOR_HOST") {
+        builder = builder.credentials(Credentials::Unauthenticated).root_url(emulator_host);
+    } else {
+        builder = builder.credentials(Credentials::ServiceAccountFile("auth/service_account2.json".to_string()));
+    }
+    builder.build().await.unwrap()
 }
 
 #[derive(Debug)]
@@ -123,6 +1353,8 @@ pub struct Infos<'a> {
     #[cfg_attr(not(man_impl="true"), db_name("info4i"))]
     int_info4: Option<i64>,
     yes: Option<bool>,
+    #[cfg_attr(not(man_impl = "true"), snapshot)]
+    snapshot: Snapshot,
 }
 
 
@@ -155,6 +1387,7 @@ impl<'a> Default for Infos<'a> {
             info3: Default::default(),
             int_info4: Default::default(),
             yes: Default::default(),
+            snapshot: Default::default(),
         }
     }
 }
@@ -165,7 +1398,7 @@ impl<'a> BigDataTableBase<'a, Infos<'a>, i64> for Infos<'a> {
         Self::get_field_name(stringify!(row_id)).unwrap()
     }
 
-    fn get_field_name(field_name: &str) -> Result<String, Box<dyn Error>> {
+    fn get_field_name(field_name: &str) -> Result<String, Error> {
         match field_name {
             "row_id" => Ok("Id".to_string()),
             "info1" => Ok("info1".to_string()),
@@ -173,7 +1406,7 @@ impl<'a> BigDataTableBase<'a, Infos<'a>, i64> for Infos<'a> {
             "info3" => Ok("info3".to_string()),
             "int_info4" => Ok("info4i".to_string()),
             "yes" => Ok("yes".to_string()),
-            _ => Err("Field not found".into()),
+            _ => Err(Error::UnknownField { field: field_name.to_string(), available_fields: vec![] }),
         }
     }
 
@@ -207,7 +1440,7 @@ impl<'a> BigDataTableBase<'a, Infos<'a>, i64> for Infos<'a> {
     fn write_from_table_row(&mut self,
                             row: &google_bigquery2::api::TableRow,
                             index_to_name_mapping: &HashMap<String, usize>)
-                            -> Result<(), Box<dyn Error>> {
+                            -> Result<(), Error> {
         let cell = row.f.as_ref().unwrap();
 
         let info1 = *index_to_name_mapping.get(Self::get_field_name(stringify!(info1))?.as_str()).unwrap();
@@ -293,7 +1526,7 @@ impl<'a> BigDataTableBase<'a, Infos<'a>, i64> for Infos<'a> {
     fn create_from_table_row(client: &'a BigqueryClient,
                              row: &google_bigquery2::api::TableRow,
                              index_to_name_mapping: &HashMap<String, usize>)
-                             -> Result<Self, Box<dyn Error>>
+                             -> Result<Self, Error>
         where
             Self: Sized
     {
@@ -311,3 +1544,42 @@ impl<'a> BigDataTableBase<'a, Infos<'a>, i64> for Infos<'a> {
     }
 }
 
+
+#[derive(Debug, Default, HasBigQueryClient, BigDataTable)]
+pub struct Versioned<'a> {
+    #[primary_key]
+    #[required]
+    id: String,
+    #[client]
+    client: Option<&'a BigqueryClient>,
+    #[version]
+    version: i64,
+    value: Option<String>,
+}
+
+#[derive(Debug, Default, HasBigQueryClient, BigDataTable)]
+#[db_name("Versioned")]
+pub struct SnapshotVersioned<'a> {
+    #[primary_key]
+    #[required]
+    id: String,
+    #[client]
+    client: Option<&'a BigqueryClient>,
+    #[version]
+    version: i64,
+    value: Option<String>,
+    #[snapshot]
+    snapshot: Snapshot,
+}
+
+#[derive(Debug, Default, HasBigQueryClient, BigDataTable)]
+pub struct TimestampVersioned<'a> {
+    #[primary_key]
+    #[required]
+    id: String,
+    #[client]
+    client: Option<&'a BigqueryClient>,
+    #[version]
+    changed: Timestamp,
+    value: Option<String>,
+}
@@ -1,7 +1,16 @@
 use std::fmt::Display;
 
+use google_bigquery2::api::QueryParameterType;
+
 pub trait ConvertTypeToBigQueryType {
     fn to_bigquery_type() -> String where Self: Sized;
+    /// The type of a query parameter of this type. Only has to be overridden by types that are not scalar.
+    fn to_bigquery_parameter_type() -> QueryParameterType where Self: Sized {
+        QueryParameterType {
+            type_: Some(Self::to_bigquery_type()),
+            ..Default::default()
+        }
+    }
 }
 
 impl ConvertTypeToBigQueryType for bool {
@@ -40,3 +49,16 @@ where T: chrono::TimeZone + Display + Send + Sync + 'static {
         "DATETIME".to_string()
     }
 }
+
+impl<T: ConvertTypeToBigQueryType> ConvertTypeToBigQueryType for Vec<T> {
+    fn to_bigquery_type() -> String {
+        "ARRAY".to_string()
+    }
+    fn to_bigquery_parameter_type() -> QueryParameterType {
+        QueryParameterType {
+            type_: Some(Self::to_bigquery_type()),
+            array_type: Some(Some(Box::new(T::to_bigquery_parameter_type()))),
+            ..Default::default()
+        }
+    }
+}
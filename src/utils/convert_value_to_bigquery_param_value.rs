@@ -3,10 +3,18 @@ use std::fmt::Display;
 use std::str::FromStr;
 
 use chrono::{NaiveDateTime, Utc};
+use google_bigquery2::api::QueryParameterValue;
 
 pub trait ConvertValueToBigqueryParamValue {
     fn to_bigquery_param_value(&self) -> String;
     fn from_bigquery_value(value :&String) -> Result<Self, Box<dyn Error>> where Self: Sized;
+    /// The value of a query parameter. Only has to be overridden by types that are not scalar.
+    fn to_bigquery_parameter_value(&self) -> QueryParameterValue {
+        QueryParameterValue {
+            value: Some(self.to_bigquery_param_value()),
+            ..Default::default()
+        }
+    }
 }
 
 impl ConvertValueToBigqueryParamValue for i64 {
@@ -35,7 +43,7 @@ impl ConvertValueToBigqueryParamValue for bool {
         }
     }
     fn from_bigquery_value(value :&String) -> Result<Self, Box<dyn Error>>  where Self: Sized{
-        match value.as_str() {
+        match value.to_uppercase().as_str() {
             "TRUE" => Ok(true),
             "FALSE" => Ok(false),
             _ => Err("Invalid value for bool".into()),
@@ -59,7 +67,7 @@ impl ConvertValueToBigqueryParamValue for chrono::DateTime<Utc> {
         let value = value.replace("T", " ").replace("Z", "");
         // let x = NaiveDateTime::from_str(&value)
         let x = NaiveDateTime::parse_from_str(&value,"%Y-%m-%d %H:%M:%S")
-            .expect(&format!("Could not parse &String to NaiveDateTime: {}", value));
+            .map_err(|e| format!("Could not parse &String to NaiveDateTime: {}: {}", value, e))?;
         let time = chrono::DateTime::<Utc>::from_utc(x, Utc);
         // let x = chrono::DateTime::parse_from_rfc3339(value)?;
         // let time = x.with_timezone(&Utc);
@@ -79,7 +87,31 @@ impl<R:ConvertValueToBigqueryParamValue> ConvertValueToBigqueryParamValue for Op
         if value == "NULL" {
             Ok(None)
         } else {
-            Ok(R::from_bigquery_value(value).ok())
+            Ok(Some(R::from_bigquery_value(value)?))
+        }
+    }
+    fn to_bigquery_parameter_value(&self) -> QueryParameterValue {
+        match self {
+            Some(x) => x.to_bigquery_parameter_value(),
+            None => QueryParameterValue::default(),
+        }
+    }
+}
+
+/// Arrays are written as JSON array of the values of their elements, e.g. `["1","2"]`.
+impl<R: ConvertValueToBigqueryParamValue> ConvertValueToBigqueryParamValue for Vec<R> {
+    fn to_bigquery_param_value(&self) -> String {
+        let values: Vec<String> = self.iter().map(|x| x.to_bigquery_param_value()).collect();
+        serde_json::to_string(&values).unwrap_or_default()
+    }
+    fn from_bigquery_value(value :&String) -> Result<Self, Box<dyn Error>>  where Self: Sized {
+        let values: Vec<String> = serde_json::from_str(value)?;
+        values.iter().map(R::from_bigquery_value).collect()
+    }
+    fn to_bigquery_parameter_value(&self) -> QueryParameterValue {
+        QueryParameterValue {
+            array_values: Some(self.iter().map(|x| x.to_bigquery_parameter_value()).collect()),
+            ..Default::default()
         }
     }
 }
@@ -2,10 +2,20 @@
 // pub use convert_bigquery_value_to_value::ConvertBigQueryValueToOptionValue2 as ConvertBigQueryValueToOptionValue;
 pub use convert_type_to_big_query_type::ConvertTypeToBigQueryType;
 pub use convert_value_to_bigquery_param_value::ConvertValueToBigqueryParamValue;
+pub use query_param::get_query_param;
+pub use struct_parameter::{get_struct_parameter_type, get_struct_parameter_value, struct_parameter_value_to_string};
+pub use table_row::get_table_row_value;
+pub use timestamp::Timestamp;
+pub use version_type::BigDataVersionType;
 
 mod convert_type_to_big_query_type;
 mod convert_value_to_bigquery_param_value;
 mod convert_bigquery_value_to_value;
+mod query_param;
+mod struct_parameter;
+mod table_row;
+mod timestamp;
+mod version_type;
 
 pub trait BigDataValueType<T>: ConvertTypeToBigQueryType + ConvertValueToBigqueryParamValue {}
 
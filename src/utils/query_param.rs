@@ -0,0 +1,15 @@
+use google_bigquery2::api::QueryParameter;
+
+use crate::utils::BigDataValueType;
+
+/// A query parameter with the name `name` (used as `@name` in the query), NULL for `None`.
+pub fn get_query_param<T: BigDataValueType<T>>(name: &str, value: &Option<T>) -> QueryParameter {
+    QueryParameter {
+        name: Some(name.to_string()),
+        parameter_type: Some(T::to_bigquery_parameter_type()),
+        parameter_value: Some(match value {
+            Some(value) => value.to_bigquery_parameter_value(),
+            None => Default::default(),
+        }),
+    }
+}
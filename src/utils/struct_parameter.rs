@@ -0,0 +1,38 @@
+use google_bigquery2::api::{QueryParameter, QueryParameterType, QueryParameterTypeStructTypes, QueryParameterValue};
+
+/// The type of a `STRUCT` query parameter with the fields (name and type) in the given order.
+pub fn get_struct_parameter_type(fields: Vec<(&str, QueryParameterType)>) -> QueryParameterType {
+    QueryParameterType {
+        type_: Some("STRUCT".to_string()),
+        struct_types: Some(fields.into_iter()
+            .map(|(name, type_)| QueryParameterTypeStructTypes {
+                name: Some(name.to_string()),
+                type_: Some(type_),
+                ..Default::default()
+            })
+            .collect()),
+        ..Default::default()
+    }
+}
+
+/// The value of a `STRUCT` query parameter with the values of the `parameters` as its fields.
+///
+/// The names of the fields are the ones of the parameters without the `__` prefix
+/// (see [`get_query_param`](crate::BigDataTableBaseConvenience::get_query_param)).
+pub fn get_struct_parameter_value(parameters: Vec<QueryParameter>) -> QueryParameterValue {
+    QueryParameterValue {
+        struct_values: Some(parameters.into_iter()
+            .map(|parameter| {
+                let name = parameter.name.unwrap_or_default();
+                let name = name.strip_prefix("__").unwrap_or(&name).to_string();
+                (name, parameter.parameter_value.unwrap_or_default())
+            })
+            .collect()),
+        ..Default::default()
+    }
+}
+
+/// The JSON of a `STRUCT` query parameter value, e.g. `{"structValues":{"id":{"value":"1"}}}`.
+pub fn struct_parameter_value_to_string(value: &QueryParameterValue) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
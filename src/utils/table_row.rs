@@ -0,0 +1,27 @@
+use std::collections::HashMap;
+
+use google_bigquery2::api::TableRow;
+
+use crate::error::Error;
+
+/// Returns the raw value of the column `field_name` in `row`, `None` if the value is NULL.
+///
+/// Fails with [`Error::Parse`] if the row does not have the column.
+pub fn get_table_row_value<'r>(row: &'r TableRow,
+                               index_to_name_mapping: &HashMap<String, usize>,
+                               struct_name: &str,
+                               field_name: &str)
+                               -> Result<Option<&'r String>, Error> {
+    let missing = |message: &str| Error::Parse {
+        struct_name: struct_name.to_string(),
+        field: field_name.to_string(),
+        value: None,
+        message: message.to_string(),
+    };
+    let index = *index_to_name_mapping.get(field_name)
+        .ok_or_else(|| missing("the query result has no column for the field"))?;
+    let cell = row.f.as_ref()
+        .and_then(|cells| cells.get(index))
+        .ok_or_else(|| missing("the row has no value for the column of the field"))?;
+    Ok(cell.v.as_ref())
+}
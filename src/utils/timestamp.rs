@@ -0,0 +1,75 @@
+use std::error::Error;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+use crate::utils::{ConvertTypeToBigQueryType, ConvertValueToBigqueryParamValue};
+
+/// The value of a `TIMESTAMP` column, with the microsecond precision BigQuery stores.
+///
+/// Unlike `DateTime<Utc>` (which is a `DATETIME` passed with second precision), it is passed to
+/// BigQuery as a `TIMESTAMP` and read from the seconds since the epoch BigQuery returns for those
+/// (e.g. `1.6961728001234E9`), so a value that was loaded compares equal to the one in the table.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(DateTime<Utc>);
+
+impl Timestamp {
+    /// The time, truncated to microseconds.
+    pub fn new(time: DateTime<Utc>) -> Self {
+        let micros = time.timestamp_micros();
+        Self(DateTime::from_timestamp_micros(micros).unwrap_or(time))
+    }
+
+    pub fn now() -> Self {
+        Self::new(Utc::now())
+    }
+
+    pub fn from_micros(micros: i64) -> Option<Self> {
+        DateTime::from_timestamp_micros(micros).map(Self)
+    }
+
+    /// The microseconds since the epoch.
+    pub fn micros(&self) -> i64 {
+        self.0.timestamp_micros()
+    }
+
+    pub fn date_time(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+impl From<DateTime<Utc>> for Timestamp {
+    fn from(time: DateTime<Utc>) -> Self {
+        Self::new(time)
+    }
+}
+
+impl From<Timestamp> for DateTime<Utc> {
+    fn from(timestamp: Timestamp) -> Self {
+        timestamp.0
+    }
+}
+
+impl ConvertTypeToBigQueryType for Timestamp {
+    fn to_bigquery_type() -> String {
+        "TIMESTAMP".to_string()
+    }
+}
+
+impl ConvertValueToBigqueryParamValue for Timestamp {
+    fn to_bigquery_param_value(&self) -> String {
+        self.0.format("%Y-%m-%d %H:%M:%S%.6f+00:00").to_string()
+    }
+    /// Reads the seconds since the epoch (as returned in query results)
+    /// or a `YYYY-MM-DD HH:MM:SS[.ffffff]` time in UTC (as written in parameters).
+    fn from_bigquery_value(value: &String) -> Result<Self, Box<dyn Error>> where Self: Sized {
+        if let Ok(seconds) = value.parse::<f64>() {
+            // a double has enough precision to tell apart the microseconds of the next few centuries
+            let micros = (seconds * 1_000_000.0).round() as i64;
+            return Self::from_micros(micros).ok_or_else(|| format!("Timestamp out of range: {}", value).into());
+        }
+        let text = value.trim_end_matches(" UTC").trim_end_matches("+00:00").trim_end_matches('Z').replace('T', " ");
+        let time = NaiveDateTime::parse_from_str(&text, "%Y-%m-%d %H:%M:%S%.f")
+            .map_err(|e| format!("Could not parse {} as TIMESTAMP: {}", value, e))?;
+        Ok(Self::new(time.and_utc()))
+    }
+}
@@ -0,0 +1,46 @@
+use crate::utils::Timestamp;
+
+/// A type that can be used for a `#[version]` field: `i64` for an INT64 column
+/// or [`Timestamp`] for a TIMESTAMP column.
+///
+/// ```compile_fail
+/// # use google_bigquery::{BigDataTableDerive, BigqueryClient, HasBigQueryClientDerive};
+/// # use google_bigquery::{BigDataTableBase, BigDataTableBaseConvenience, BigDataTableHasPk, HasBigQueryClient};
+/// # use google_bigquery::utils::ConvertValueToBigqueryParamValue;
+/// #[derive(Default, HasBigQueryClientDerive, BigDataTableDerive)]
+/// struct Infos<'a> {
+///     #[primary_key]
+///     #[required]
+///     id: String,
+///     #[client]
+///     client: Option<&'a BigqueryClient>,
+///     #[version]
+///     changed: String,
+/// }
+/// ```
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can not be used for a `#[version]` field",
+    note = "use an `i64` (INT64 column) or a `google_bigquery::utils::Timestamp` (TIMESTAMP column) as version"
+)]
+pub trait BigDataVersionType: Clone {
+    /// The version a row gets when it is saved.
+    fn next_version(&self) -> Self;
+}
+
+impl BigDataVersionType for i64 {
+    fn next_version(&self) -> Self {
+        self + 1
+    }
+}
+
+impl BigDataVersionType for Timestamp {
+    /// The current time, but always at least a microsecond (the precision of a TIMESTAMP)
+    /// later than the previous version, so that two saves never write the same version.
+    fn next_version(&self) -> Self {
+        let now = Timestamp::now();
+        match Timestamp::from_micros(self.micros() + 1) {
+            Some(next) if next > now => next,
+            _ => now,
+        }
+    }
+}